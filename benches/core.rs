@@ -0,0 +1,192 @@
+#[macro_use] extern crate criterion;
+extern crate pinggame;
+extern crate middleman;
+extern crate rand;
+
+use criterion::{
+	Benchmark,
+	Criterion,
+	Throughput,
+};
+use middleman::PackedMessage;
+use rand::{
+	Rng,
+	SeedableRng,
+	XorShiftRng,
+};
+use std::{
+	char,
+	sync::Arc,
+	time::Duration,
+};
+
+use pinggame::clock::ManualClock;
+use pinggame::common::*;
+use pinggame::game::*;
+use pinggame::mapfile::MapFile;
+use pinggame::mapgen::MapGen;
+use pinggame::server::{
+	Server,
+	ServerConfig,
+};
+use pinggame::transport::{
+	MemoryTransport,
+	Transport,
+};
+
+/*
+The paths every tick or every join goes through. Maps are generated from
+fixed seeds so runs compare, though sync_rng and thread_rng still vary.
+*/
+
+const SIDES: [u16; 3] = [30, 100, 200];
+
+// of the cells inside the border
+const WALL_PERCENTS: [u32; 3] = [10, 50, 90];
+
+const CLIENT_COUNTS: [usize; 3] = [10, 100, 500];
+
+fn rng() -> XorShiftRng {
+	SeedableRng::from_seed([1, 2, 3, 4])
+}
+
+fn generated(side: u16) -> GameState {
+	let map = MapSource::Generated {
+		seed: [5, 6, 7, 8],
+		gen: MapGen::default(),
+		bounds: Bounds::new(side, side),
+	};
+	GameState::new(map, None)
+}
+
+// monikers for everyone, however many there are
+fn moniker(i: usize) -> Moniker {
+	Moniker(char::from_u32(0x100 + i as u32).unwrap())
+}
+
+// a generated map with a player per 100 cells, and some walls pushed around
+fn busy(side: u16) -> GameState {
+	let mut game_state = generated(side);
+	let mut rng = rng();
+	let players = game_state.total_coords() / 100;
+	for i in 0..players {
+		let team = if i % 2 == 0 { Team::Red } else { Team::Blue };
+		if let Some(coord) = game_state.random_free_spot_for(team) {
+			game_state.try_add_player(moniker(i), coord, team);
+		}
+	}
+	for _ in 0..players * 20 {
+		let i = rng.gen_range(0, players);
+		game_state.move_moniker_in_dir(moniker(i), *rng.choose(&DIR_CHOICES).unwrap());
+	}
+	game_state
+}
+
+// an open map of `side` by `side` with a share of its inside walled up
+fn walled(side: u16, percent: u32) -> GameState {
+	let mut map = MapFile::empty(side, side);
+	let bounds = Bounds::new(side, side);
+	let mut rng = rng();
+	map.walls.extend(bounds.coord_iter()
+		.filter(|&coord| !bounds.on_boundary(coord) && rng.gen_range(0, 100) < percent));
+	GameState::new(MapSource::Authored(map), None)
+}
+
+fn from_essence(c: &mut Criterion) {
+	for &side in SIDES.iter() {
+		let essence = busy(side).get_essence().clone();
+		c.bench_function(&format!("from_essence {}x{}", side, side), move |b| b.iter(|| {
+			GameState::from_essence(essence.clone()).unwrap()
+		}));
+	}
+}
+
+fn free_spots(c: &mut Criterion) {
+	for &percent in WALL_PERCENTS.iter() {
+		let game_state = walled(100, percent);
+		// how full a map is, not how many walls it was given, is what picks the search
+		let empty = format!("{}% walls, {:.2} empty", percent, game_state.empty_cell_ratio());
+		c.bench_function(&format!("random_free_spot {}", empty), move |b| b.iter(|| {
+			game_state.random_free_spot()
+		}));
+		let mut game_state = walled(100, percent);
+		c.bench_function(&format!("sync_random_free_spot {}", empty), move |b| b.iter(|| {
+			game_state.sync_random_free_spot()
+		}));
+	}
+}
+
+fn moves(c: &mut Criterion) {
+	for &side in SIDES.iter() {
+		let mut game_state = walled(side, 0);
+		let me = Moniker('a');
+		let coord = game_state.random_free_spot_for(Team::Red).unwrap();
+		assert!(game_state.try_add_player(me, coord, Team::Red));
+		let dir = if coord.x < side / 2 { Direction::Right } else { Direction::Left };
+		// back and forth, picking up whatever blobs turn up on the way
+		c.bench_function(&format!("move_moniker_in_dir {}x{}", side, side), move |b| b.iter(|| {
+			game_state.move_moniker_in_dir(me, dir);
+			game_state.move_moniker_in_dir(me, dir.opposite())
+		}));
+	}
+}
+
+fn welcome(c: &mut Criterion) {
+	for &side in SIDES.iter() {
+		let essence = busy(side).get_essence().clone();
+		let bytes = PackedMessage::new(&Clientward::Welcome(essence.clone())).unwrap().byte_len();
+		let bench = Benchmark::new(format!("{}x{}", side, side), move |b| b.iter(|| {
+			PackedMessage::new(&Clientward::Welcome(essence.clone())).unwrap()
+		}));
+		c.bench("pack Welcome", bench.throughput(Throughput::Bytes(bytes as u32)));
+	}
+}
+
+// throws away whatever has landed at each client
+fn drain(clients: &mut [MemoryTransport]) {
+	for client in clients.iter_mut() {
+		while let Ok(Some(_)) = client.recv() {}
+	}
+}
+
+/*
+One server step in which a single move goes out to every client. That's
+mostly broadcast_outgoing_updates, plus emptying the clients' inboxes so they
+don't grow from one iteration to the next.
+*/
+fn broadcast(c: &mut Criterion) {
+	for &count in CLIENT_COUNTS.iter() {
+		let clock = ManualClock::new();
+		let config = ServerConfig {
+			map_file: Some(MapFile::empty(50, 50)),
+			..ServerConfig::default()
+		};
+		let mut server = Server::new(config, Arc::new(clock.clone())).unwrap();
+		let mut clients: Vec<MemoryTransport> = (0..count).map(|i| {
+			let mut client = server.connect_memory();
+			client.send_msg(&Serverward::Hello(moniker(i), None)).unwrap();
+			client
+		}).collect();
+		server.step_within(Some(Duration::from_millis(0)));
+		drain(&mut clients);
+		assert_eq!(server.game_state().num_players(), count);
+
+		let mover = moniker(0);
+		let coord = server.game_state().get_player(mover).unwrap().coord;
+		let mut dir = DIR_CHOICES.iter().cloned().find(|&dir| {
+			let dest = coord.move_with(dir);
+			!server.game_state().is_something_at(dest)
+			&& server.game_state().team_may_enter(server.game_state().get_player(mover).unwrap().team, dest)
+		}).expect("boxed in");
+		c.bench_function(&format!("broadcast a move to {} clients", count), move |b| b.iter(|| {
+			clock.advance(MOVE_PERIOD);
+			clients[0].send_msg(&Serverward::ReqMove(dir)).unwrap();
+			server.step_within(Some(Duration::from_millis(0)));
+			drain(&mut clients);
+			dir = dir.opposite();
+		}));
+	}
+}
+
+criterion_group!(benches, from_essence, free_spots, moves, welcome, broadcast);
+criterion_main!(benches);
@@ -0,0 +1,134 @@
+#[macro_use] extern crate criterion;
+extern crate pinggame;
+extern crate bincode;
+extern crate bitset;
+extern crate rand;
+
+use criterion::{
+	Benchmark,
+	Criterion,
+	Throughput,
+};
+use bitset::BitSet;
+use rand::{
+	Rng,
+	SeedableRng,
+	XorShiftRng,
+};
+use std::collections::HashMap;
+
+use pinggame::game::{
+	Bounds,
+	Coord2D,
+	GameState,
+	MapSource,
+};
+use pinggame::grid::{
+	BitGrid,
+	WallFlips,
+};
+use pinggame::mapgen::MapGen;
+
+/*
+Walls before and after the move to a dense grid. `Legacy` is how they used
+to be stored: one BitSet per row for the map's layout, and a HashMap of
+every wall that was ever pushed, which also went over the wire in full.
+*/
+
+const SIDES: [u16; 3] = [30, 100, 200];
+
+// walls pushed around, per 100 cells
+const PUSHED_PER_100: usize = 5;
+
+struct Legacy {
+	wall_default: Vec<BitSet>,
+	wall_override: HashMap<Coord2D, bool>,
+}
+impl Legacy {
+	fn is_wall_at(&self, coord: Coord2D) -> bool {
+		self.wall_override.get(&coord)
+		.copied()
+		.unwrap_or_else(|| {
+			self.wall_default[coord.y as usize]
+			.test(coord.x as usize)
+		})
+	}
+}
+
+fn rng() -> XorShiftRng {
+	SeedableRng::from_seed([1, 2, 3, 4])
+}
+
+// the same walls, both ways
+fn build(side: u16) -> (Legacy, BitGrid, WallFlips) {
+	let bounds = Bounds::new(side, side);
+	let mut rng = rng();
+	let mut legacy = Legacy {
+		wall_default: (0..side).map(|_| BitSet::with_capacity(side as usize)).collect(),
+		wall_override: HashMap::new(),
+	};
+	let mut grid = BitGrid::new(side, side, false);
+	for coord in bounds.coord_iter() {
+		if rng.gen_weighted_bool(3) {
+			legacy.wall_default[coord.y as usize].set(coord.x as usize, true);
+			grid.set(coord.x, coord.y, true);
+		}
+	}
+	let mut flips = WallFlips::new();
+	let pushed = bounds.total_coords() * PUSHED_PER_100 / 100;
+	while flips.len() < pushed {
+		let coord = Coord2D::new(rng.gen_range(0, side), rng.gen_range(0, side));
+		let index = bounds.index_of(coord) as u32;
+		if flips.contains(index) {
+			continue;
+		}
+		let now = !grid.get(coord.x, coord.y);
+		legacy.wall_override.insert(coord, now);
+		grid.set(coord.x, coord.y, now);
+		flips.toggle(index);
+	}
+	(legacy, grid, flips)
+}
+
+fn is_wall_at(c: &mut Criterion) {
+	for &side in SIDES.iter() {
+		let (legacy, grid, _) = build(side);
+		let bounds = Bounds::new(side, side);
+		c.bench_function(&format!("is_wall_at legacy {}x{}", side, side), move |b| b.iter(|| {
+			bounds.coord_iter().filter(|&coord| legacy.is_wall_at(coord)).count()
+		}));
+		c.bench_function(&format!("is_wall_at grid {}x{}", side, side), move |b| b.iter(|| {
+			bounds.coord_iter().filter(|&coord| grid.get(coord.x, coord.y)).count()
+		}));
+	}
+	let map = MapSource::Generated {
+		seed: [5, 6, 7, 8],
+		gen: MapGen::default(),
+		bounds: Bounds::new(200, 200),
+	};
+	let game_state = GameState::new(map, None);
+	c.bench_function("GameState::is_wall_at 200x200", move |b| b.iter(|| {
+		game_state.coord_iter().filter(|&coord| game_state.is_wall_at(coord)).count()
+	}));
+}
+
+fn wall_serialization(c: &mut Criterion) {
+	for &side in SIDES.iter() {
+		let (legacy, _, flips) = build(side);
+		// each with its size as throughput, so the report compares bytes on the wire too
+		let legacy_bytes = bincode::serialize(&legacy.wall_override).unwrap().len();
+		let flips_bytes = bincode::serialize(&flips).unwrap().len();
+		let overrides = legacy.wall_override;
+		let bench = Benchmark::new(format!("legacy overrides {}x{}", side, side), move |b| b.iter(|| {
+			bincode::serialize(&overrides).unwrap()
+		}));
+		c.bench("serialize walls", bench.throughput(Throughput::Bytes(legacy_bytes as u32)));
+		let bench = Benchmark::new(format!("flips {}x{}", side, side), move |b| b.iter(|| {
+			bincode::serialize(&flips).unwrap()
+		}));
+		c.bench("serialize walls", bench.throughput(Throughput::Bytes(flips_bytes as u32)));
+	}
+}
+
+criterion_group!(benches, is_wall_at, wall_serialization);
+criterion_main!(benches);
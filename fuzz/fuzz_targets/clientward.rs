@@ -0,0 +1,70 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate middleman;
+extern crate pinggame;
+
+use middleman::PackedMessage;
+use pinggame::common::{
+	Clientward,
+	DIR_CHOICES,
+};
+use pinggame::game::*;
+use pinggame::mapgen::MapGen;
+use pinggame::snapshot::{
+	SnapshotBuffer,
+	WELCOME_SNAPSHOT,
+};
+
+// the game a client is in when a snapshot arrives
+fn welcomed() -> GameStateEssence {
+	let map = MapSource::Generated {
+		seed: [1, 2, 3, 4],
+		gen: MapGen::NAMES[0].parse().unwrap(),
+		bounds: Bounds::new(16, 16),
+	};
+	GameState::new(map, None).get_essence().clone()
+}
+
+// what a client goes on to do with the state: look around and replay moves.
+// an essence it can't build a game from is refused
+fn play_on(essence: GameStateEssence) {
+	let mut game_state = match GameState::from_essence(essence) {
+		Ok(game_state) => game_state,
+		Err(_) => return,
+	};
+	let monikers: Vec<Moniker> = game_state.player_iter().map(|(&m, _)| m).collect();
+	for &moniker in monikers.iter() {
+		game_state.essence_visible_to(moniker);
+		for &dir in DIR_CHOICES.iter() {
+			game_state.move_moniker_in_dir(moniker, dir);
+		}
+	}
+}
+
+/*
+Whatever a server sends, decoding it must fail cleanly or give something that
+packs again. Whole essences, map and all, and snapshots are then applied the
+way a client applies them: refused, or played on without panicking.
+*/
+fuzz_target!(|data: &[u8]| {
+	let len = data.len() as u32;
+	let mut raw = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+	raw.extend_from_slice(data);
+	if let Ok(msg) = PackedMessage::from_raw(raw).unpack::<Clientward>() {
+		msg.kind();
+		let packed = PackedMessage::new(&msg).expect("decoded but won't pack");
+		packed.unpack::<Clientward>().expect("packed but won't decode");
+		match msg {
+			Clientward::Welcome(essence) | Clientward::Refresh(essence) => play_on(essence),
+			Clientward::Snapshot(id, base_id, delta) => {
+				let mut snapshots = SnapshotBuffer::new(welcomed());
+				// only deltas against the welcome have a base here
+				if let Some(essence) = snapshots.resolve(id, base_id, &delta) {
+					assert!(base_id == WELCOME_SNAPSHOT);
+					play_on(essence);
+				}
+			},
+			_ => (),
+		}
+	}
+});
@@ -0,0 +1,43 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate pinggame;
+
+use pinggame::common::DIR_CHOICES;
+use pinggame::game::*;
+use pinggame::mapfile::MapFile;
+use pinggame::mapgen::MapGen;
+
+/*
+The first bytes pick a map, and every byte after that is a join, a leave or a
+move. Joins take two more bytes for where, which may well be off the map, as
+a client can be told anything. Nothing may panic, and the state must keep its
+invariants throughout.
+*/
+fuzz_target!(|data: &[u8]| {
+	if data.len() < 4 {
+		return;
+	}
+	let map = MapSource::Generated {
+		seed: [data[0] as u32 + 1, 2, 3, 4],
+		gen: MapGen::NAMES[data[1] as usize % MapGen::NAMES.len()].parse().unwrap(),
+		bounds: Bounds::new(MapFile::MIN_SIZE + data[2] as u16 % 40, MapFile::MIN_SIZE + data[3] as u16 % 40),
+	};
+	let mut game_state = GameState::new(map, None);
+	let mut bytes = data[4..].iter().cloned();
+	while let Some(b) = bytes.next() {
+		let moniker = Moniker((b'a' + (b >> 2) % 8) as char);
+		match b >> 5 {
+			0 => {
+				let (x, y) = match (bytes.next(), bytes.next()) {
+					(Some(x), Some(y)) => (x as u16, y as u16),
+					_ => return,
+				};
+				let team = if b & 1 == 0 { Team::Red } else { Team::Blue };
+				game_state.try_add_player(moniker, Coord2D::new(x, y), team);
+			},
+			1 => { game_state.try_remove_player(moniker); },
+			_ => { game_state.move_moniker_in_dir(moniker, DIR_CHOICES[(b & 3) as usize]); },
+		}
+		game_state.assert_invariants();
+	}
+});
@@ -0,0 +1,19 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate middleman;
+extern crate pinggame;
+
+use middleman::PackedMessage;
+use pinggame::common::Serverward;
+
+// whatever a client sends, decoding it must fail cleanly or give something that packs again
+fuzz_target!(|data: &[u8]| {
+	let len = data.len() as u32;
+	let mut raw = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+	raw.extend_from_slice(data);
+	if let Ok(msg) = PackedMessage::from_raw(raw).unpack::<Serverward>() {
+		msg.kind();
+		let packed = PackedMessage::new(&msg).expect("decoded but won't pack");
+		packed.unpack::<Serverward>().expect("packed but won't decode");
+	}
+});
@@ -0,0 +1,139 @@
+use ::common::{
+	secs_f32,
+	MARK_SHOW_TIME,
+};
+use ::game::*;
+
+use std::time::{
+	Duration,
+	Instant,
+};
+
+/////////////////////////////////////////////////////////////
+
+pub const STEP_TIME: Duration = Duration::from_millis(80); // shorter than MOVE_PERIOD
+pub const WALL_SLIDE_TIME: Duration = Duration::from_millis(120);
+pub const PICKUP_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Copy, Clone, Debug)]
+pub enum Effect {
+	WallSlide { from: Coord2D, to: Coord2D }, // a pushed wall on its way
+	Pickup { at: Coord2D, kind: PickupKind }, // a blob bursting
+	Mark { at: Coord2D, from: Moniker }, // someone's map ping
+}
+impl Effect {
+	fn duration(self) -> Duration {
+		match self {
+			Effect::WallSlide { .. } => WALL_SLIDE_TIME,
+			Effect::Pickup { .. } => PICKUP_TIME,
+			Effect::Mark { .. } => MARK_SHOW_TIME,
+		}
+	}
+}
+
+/*
+Purely cosmetic: the game state has already moved on, and these just make it
+look that way gradually. My own square takes a short step to its new cell
+(everyone else is handled by Interpolator), pushed walls slide, picked up
+blobs burst and map pings fade. Nothing is animating once every effect has run
+out.
+*/
+#[derive(Debug)]
+pub struct Animations {
+	my_moniker: Moniker,
+	my_step: Option<(Coord2D, Coord2D, Instant)>,
+	effects: Vec<(Effect, Instant)>,
+}
+impl Animations {
+	pub fn new(my_moniker: Moniker) -> Self {
+		Animations {
+			my_moniker,
+			my_step: None,
+			effects: vec![],
+		}
+	}
+
+	// makes the move on `game_state`, starting whatever animations it calls for
+	pub fn animate_move(&mut self, game_state: &mut GameState, moniker: Moniker,
+		                dir: Direction, now: Instant) -> ValidMove {
+		let src = match game_state.get_player(moniker) {
+			Some(player) => player.coord,
+			None => return false,
+		};
+		let dest = if game_state.coord_would_exit(src, dir) { None } else { Some(src.move_with(dir)) };
+		let wall_ahead = dest.map(|c| game_state.is_wall_at(c)).unwrap_or(false);
+		let blobs_before: Vec<(Coord2D, PickupKind)> = game_state.blob_iter()
+			.map(|(&c, &k)| (c, k))
+			.collect();
+
+		if !game_state.move_moniker_in_dir(moniker, dir) {
+			return false;
+		}
+
+		if let Some(dest) = dest {
+			if wall_ahead && !game_state.is_wall_at(dest) {
+				let to = dest.move_with(dir); // a pushed wall never leaves the board
+				self.effects.push((Effect::WallSlide { from: dest, to }, now));
+			}
+		}
+		for &(at, kind) in blobs_before.iter() {
+			if !game_state.is_blob_at(at) {
+				self.effects.push((Effect::Pickup { at, kind }, now));
+			}
+		}
+		if moniker == self.my_moniker {
+			let now_at = game_state.get_player(moniker).unwrap().coord;
+			self.my_step = if Some(now_at) == dest { Some((src, now_at, now)) } else { None };
+		}
+		true
+	}
+
+	pub fn mark(&mut self, from: Moniker, at: Coord2D, now: Instant) {
+		self.effects.push((Effect::Mark { at, from }, now));
+	}
+
+	// where to draw me, in fractions of a cell
+	pub fn my_position(&self, game_state: &GameState, now: Instant) -> Option<(f32, f32)> {
+		let at = game_state.get_player(self.my_moniker)?.coord;
+		if let Some((from, to, started)) = self.my_step {
+			if to == at {
+				let t = progress(started, STEP_TIME, now);
+				return Some(lerp(from, to, t));
+			}
+		}
+		Some((at.x as f32, at.y as f32))
+	}
+
+	// effects still running, with how far along they are from 0 to 1
+	pub fn active(&self, now: Instant) -> Vec<(Effect, f32)> {
+		self.effects.iter()
+			.map(|&(effect, started)| (effect, progress(started, effect.duration(), now)))
+			.filter(|&(_, t)| t < 1.0)
+			.collect()
+	}
+
+	pub fn is_animating(&self, now: Instant) -> bool {
+		self.my_step.map(|(_, _, started)| progress(started, STEP_TIME, now) < 1.0).unwrap_or(false)
+		|| self.effects.iter().any(|&(effect, started)| progress(started, effect.duration(), now) < 1.0)
+	}
+
+	pub fn expire(&mut self, now: Instant) {
+		self.effects.retain(|&(effect, started)| progress(started, effect.duration(), now) < 1.0);
+	}
+}
+
+pub fn lerp(from: Coord2D, to: Coord2D, t: f32) -> (f32, f32) {
+	(
+		from.x as f32 + (to.x as f32 - from.x as f32) * t,
+		from.y as f32 + (to.y as f32 - from.y as f32) * t,
+	)
+}
+
+// 0 when started, 1 once `duration` has passed
+fn progress(started: Instant, duration: Duration, now: Instant) -> f32 {
+	if now <= started {
+		return 0.0;
+	}
+	let t = secs_f32(now - started) / secs_f32(duration);
+	t.min(1.0)
+}
@@ -0,0 +1,74 @@
+use std::{
+	collections::VecDeque,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+pub const CHAT_MAX_CHARS: usize = 160;
+pub const CHAT_PERIOD: Duration = Duration::from_millis(500); // the least time between one player's messages
+pub const CHAT_LOG_LINES: usize = 6; // kept by clients
+pub const CHAT_SHOW_TIME: Duration = Duration::from_secs(10); // lines disappear after this, unless typing
+
+// the server logs every chat message here, so it can be given its own level
+pub const CHAT_LOG_TARGET: &str = "pinggame::chat";
+
+// what's fit to pass on of a message: one trimmed line of at most CHAT_MAX_CHARS, if anything's left
+pub fn clean(text: &str) -> Option<String> {
+	let cleaned: String = text.trim_start().chars()
+		.filter(|c| !c.is_control())
+		.take(CHAT_MAX_CHARS)
+		.collect();
+	let cleaned = cleaned.trim_end();
+	if cleaned.is_empty() {
+		None
+	} else {
+		Some(cleaned.to_owned())
+	}
+}
+
+/*
+The last few chat lines a client has received, in whatever form it draws them
+in, with when each arrived. Lines are shown for a while after arriving, and
+all of them are shown while the player is typing.
+*/
+#[derive(Debug)]
+pub struct ChatLog<T> {
+	lines: VecDeque<(Instant, T)>,
+}
+impl<T> ChatLog<T> {
+	pub fn new() -> Self {
+		ChatLog { lines: VecDeque::new() }
+	}
+
+	pub fn push(&mut self, line: T, now: Instant) {
+		if self.lines.len() == CHAT_LOG_LINES {
+			self.lines.pop_front();
+		}
+		self.lines.push_back((now, line));
+	}
+
+	// how many of the newest lines to show
+	pub fn shown(&self, now: Instant, typing: bool) -> usize {
+		if typing {
+			return self.lines.len();
+		}
+		self.lines.iter().rev()
+			.take_while(|&&(at, _)| now.duration_since(at) < CHAT_SHOW_TIME)
+			.count()
+	}
+
+	// the lines to show, oldest first
+	pub fn visible(&self, now: Instant, typing: bool) -> Vec<&T> {
+		let skip = self.lines.len() - self.shown(now, typing);
+		self.lines.iter().skip(skip).map(|(_, line)| line).collect()
+	}
+}
+impl<T> Default for ChatLog<T> {
+	fn default() -> Self {
+		ChatLog::new()
+	}
+}
@@ -1,5 +1,21 @@
 use ::common::*;
 use ::game::*;
+use ::snapshot::SnapshotBuffer;
+use ::interp::Interpolator;
+use ::anim::{
+	Animations,
+	Effect,
+	lerp,
+};
+use ::netstats::{
+	NetStats,
+	RTT_HISTORY,
+};
+use ::chat::{
+	self,
+	ChatLog,
+	CHAT_MAX_CHARS,
+};
 
 use std::{
 	net::SocketAddr,
@@ -10,7 +26,10 @@ use std::{
 	collections::HashMap,
 };
 
-use middleman::Middleman;
+use middleman::{
+	Middleman,
+	PackedMessage,
+};
 
 use mio::{
     Poll,
@@ -24,17 +43,20 @@ use ggez::{
     Context,
     GameResult,
     conf,
+    timer,
     graphics::{
         self,
         Color,
         DrawMode,
         Point2,
         Mesh,
+        DrawParam,
     },
     event::{
         self,
         Keycode,
         Mod,
+        MouseButton,
     },
 };
 
@@ -42,6 +64,10 @@ type TextCache = HashMap<Moniker, graphics::Text>;
 
 const CLIENT_TOKEN: Token = Token(0);
 
+// how often the client looks at the network and redraws, if anything changed
+const TARGET_FPS: u32 = 60;
+const IDLE_SLEEP: Duration = Duration::from_millis(2);
+
 const CHARGE_COLORS: [Color; 4] = [
 	Color { r:1.0, g:1.0, b:1.0, a:1. },
 	Color { r:1.0, g:0.8, b:0.8, a:1. },
@@ -49,37 +75,94 @@ const CHARGE_COLORS: [Color; 4] = [
 	Color { r:1.0, g:0.0, b:1.0, a:1. },
 ];
 
-pub fn client_enter(addr: &SocketAddr, my_moniker: Moniker) {
-	println!("Client starting, for server at addr {:?}!", addr);
+const TEAM_COLORS: [Color; 2] = [
+	Color { r:0.9, g:0.2, b:0.2, a:1. },
+	Color { r:0.2, g:0.4, b:1.0, a:1. },
+];
+
+pub const BASE_COLORS: [Color; 2] = [
+	Color { r:0.25, g:0.05, b:0.05, a:1. },
+	Color { r:0.05, g:0.08, b:0.3, a:1. },
+];
+
+// indexed by PickupKind::index
+pub const PICKUP_COLORS: [Color; 5] = [
+	Color { r:1.0, g:1.0, b:0.0, a:1. }, // charge
+	Color { r:0.0, g:1.0, b:1.0, a:1. }, // speed
+	Color { r:0.7, g:0.7, b:0.9, a:1. }, // ghost
+	Color { r:1.0, g:0.5, b:0.0, a:1. }, // teleport
+	Color { r:0.5, g:0.3, b:0.1, a:1. }, // wall spawner
+];
+
+const PORTAL_COLOR: Color = Color { r:0.6, g:0.2, b:0.8, a:1. };
+const CONVEYOR_COLOR: Color = Color { r:0.3, g:0.3, b:0.3, a:1. };
+pub const CONVEYOR_ARROW_COLOR: Color = Color { r:0.7, g:0.7, b:0.7, a:1. };
+const ICE_COLOR: Color = Color { r:0.75, g:0.9, b:1.0, a:1. };
+const PIT_COLOR: Color = Color { r:0.1, g:0.1, b:0.1, a:1. };
+
+pub const WALL_COLOR: Color = Color { r:40./255., g:0.0, b:0.0, a:1. };
+
+// fraction of a cell the arrow marker on a conveyor occupies
+const CONVEYOR_ARROW_SCALE: f32 = 0.3;
+
+const FOG_COLOR: Color = Color { r:0.0, g:0.0, b:0.0, a:0.75 };
+
+const GHOST_ALPHA: f32 = 0.5;
+
+// spawn protected players get a white rim around their team colour
+const PROTECTED_RIM_COLOR: Color = Color { r:1.0, g:1.0, b:1.0, a:1. };
+const PROTECTED_INSET_SCALE: f32 = 0.8;
+
+// fraction of a cell the inner charge-coloured square occupies
+const CHARGE_INSET_SCALE: f32 = 0.6;
+
+// how big a picked up blob grows while it fades away
+const PICKUP_BURST_SCALE: f32 = 1.8;
+
+// map pings: a frame around the cell that closes in on it as it fades, over the fog
+const MARK_COLOR: Color = Color { r:1.0, g:0.9, b:0.2, a:1. };
+const MARK_START_SCALE: f32 = 2.5;
+const MARK_RIM: f32 = 0.15; // the frame's thickness, in cells
+
+// the network overlay, toggled with F3
+const HUD_REFRESH: Duration = Duration::from_millis(250); // how often its text is rebuilt
+const HUD_BACKDROP_COLOR: Color = Color { r:0.0, g:0.0, b:0.0, a:0.7 };
+const HUD_GRAPH_COLOR: Color = Color { r:0.3, g:1.0, b:0.3, a:1. };
+const HUD_LINE_HEIGHT: f32 = 16.0;
+const HUD_WIDTH: f32 = 300.0;
+const HUD_GRAPH_HEIGHT: f32 = 60.0;
+const HUD_GRAPH_MIN_SCALE_MS: f32 = 50.0; // the graph's top, unless round trips get longer
+
+// chat, bottom left. Enter starts and sends a message
+const CHAT_WIDTH: f32 = 480.0;
+
+pub fn client_enter(addr: &SocketAddr, my_moniker: Moniker, my_team: Option<Team>,
+	                interp_delay: Duration) {
+	info!("client starting addr={} moniker={}", addr, my_moniker.0);
 	match StdStream::connect(addr) {
 		Ok(stream) => {
 			stream.set_nodelay(true).expect("set_nodelay call failed");
 			let mm = Middleman::new(MioStream::from_stream(stream).unwrap());
-			client_go(mm, my_moniker);
+			client_go(mm, my_moniker, my_team, interp_delay);
 		},
 		Err(e) => {
-			println!("Failed to connect to addr `{:?}`. Got err {:?}", addr, e);
+			error!("connect failed addr={} err={}", addr, e);
 		}
 	}
 }
 
-fn client_go(mut mm: Middleman, my_moniker: Moniker) {
+fn client_go(mut mm: Middleman, my_moniker: Moniker, my_team: Option<Team>, interp_delay: Duration) {
 	let poll = Poll::new().unwrap();
 	let mut events = Events::with_capacity(128);
 	poll.register(&mm, CLIENT_TOKEN,
     			Ready::readable(),
     			PollOpt::edge()).unwrap();
 
-	mm.send(& Serverward::Hello(my_moniker)).expect("HELLO send fail");
-	use common::Clientward::*;
-	let game_state = match mm.recv_blocking_solo::<Clientward>(&poll, &mut events, None).expect("crash").unwrap() {
-		Welcome(essence) => GameState::from_essence(essence),
-		msg => {
-			println!("Got unexpected server msg {:?}", msg);
-			panic!("Server Hello went awry");
-		},
+	let (snapshots, game_state) = match client_handshake(&mut mm, &poll, &mut events, my_moniker, my_team) {
+		Some(welcomed) => welcomed,
+		None => return,
 	};
-	println!("Initial game state {:?}", &game_state);
+	debug!("welcomed moniker={} players={} bounds={:?}", my_moniker.0, game_state.num_players(), game_state.bounds());
 
 	let c = conf::Conf::new();
     let ctx = &mut Context::load_from_conf("super_simple", "ggez", c).unwrap();
@@ -90,26 +173,39 @@ fn client_go(mut mm: Middleman, my_moniker: Moniker) {
     }
     insert_into_cache(ctx, &mut text_cache, my_moniker);
     let (w, h) = graphics::get_size(ctx);
+    let mesh = build_square_mesh(ctx, game_state.bounds()).unwrap();
+    let mut interp = Interpolator::new(interp_delay);
+    interp.record(&game_state, Instant::now());
     let mut cs = ClientState {
-        game_state: game_state,
-        mm: mm,
+        my_moniker,
+        game_state,
+        mm,
         screen_dims: [w, h],
-        poll: poll,
-        events: events,
-        mesh: build_square_mesh(ctx).unwrap(),
+        poll,
+        events,
+        mesh,
         poll_timeout: Some(Duration::from_millis(0)),
         no_change: false,
-        text_cache: text_cache,
+        text_cache,
+        score_text: None,
         last_move_at: Instant::now(),
+        snapshots,
+        interp,
+        anims: Animations::new(my_moniker),
+        net_stats: NetStats::new(),
+        hud: None,
+        chat_log: ChatLog::new(),
+        chat_input: None,
+        chat_shown: 0,
     };
     event::run(ctx, &mut cs).unwrap();
 }
 
-fn build_square_mesh(ctx: &mut Context) -> GameResult<Mesh> {
+pub fn build_square_mesh(ctx: &mut Context, bounds: Bounds) -> GameResult<Mesh> {
 	let mb = &mut graphics::MeshBuilder::new();
     let (w, h) = graphics::get_size(ctx);
-    let w1 = (w / GameState::WIDTH as u32) as f32;
-    let h1 = (h / GameState::HEIGHT as u32) as f32;
+    let w1 = (w / bounds.width as u32) as f32;
+    let h1 = (h / bounds.height as u32) as f32;
     mb.polygon(
     	DrawMode::Fill,
     	&[
@@ -122,6 +218,34 @@ fn build_square_mesh(ctx: &mut Context) -> GameResult<Mesh> {
     mb.build(ctx)
 }
 
+pub fn tile_color(tile: Tile) -> Color {
+	match tile {
+		Tile::Portal(_) => PORTAL_COLOR,
+		Tile::Conveyor(_) => CONVEYOR_COLOR,
+		Tile::Ice => ICE_COLOR,
+		Tile::Pit => PIT_COLOR,
+	}
+}
+
+// a small square pushed up against the edge the conveyor flows toward
+pub fn conveyor_arrow_param(screen_point: Point2, cell_size: (f32, f32), dir: Direction) -> DrawParam {
+	let slack = 1.0 - CONVEYOR_ARROW_SCALE;
+	let (fx, fy) = match dir {
+		Direction::Up => (slack / 2.0, 0.0),
+		Direction::Down => (slack / 2.0, slack),
+		Direction::Left => (0.0, slack / 2.0),
+		Direction::Right => (slack, slack / 2.0),
+	};
+	DrawParam {
+		dest: Point2::new(
+			screen_point.x + cell_size.0 * fx,
+			screen_point.y + cell_size.1 * fy,
+		),
+		scale: Point2::new(CONVEYOR_ARROW_SCALE, CONVEYOR_ARROW_SCALE),
+		.. Default::default()
+	}
+}
+
 fn insert_into_cache(ctx: &mut Context, text_cache: &mut TextCache, moniker: Moniker) {
 	text_cache.insert(
     	moniker,
@@ -133,7 +257,15 @@ fn insert_into_cache(ctx: &mut Context, text_cache: &mut TextCache, moniker: Mon
     );
 }
 
+// every message to the server goes through here, so it's counted for the HUD
+fn send_counted(mm: &mut Middleman, stats: &mut NetStats, msg: &Serverward) {
+	let packed = PackedMessage::new(msg).expect("failed to pack");
+	stats.on_sent(packed.byte_len(), Instant::now());
+	mm.send_packed(& packed).expect("send fail");
+}
+
 struct ClientState {
+	my_moniker: Moniker,
 	screen_dims: [u32; 2],
     game_state: GameState,
     mm: Middleman,
@@ -143,60 +275,375 @@ struct ClientState {
     poll_timeout: Option<Duration>,
     no_change: bool,
     text_cache: TextCache,
+    score_text: Option<([u32; 2], graphics::Text)>,
     last_move_at: Instant,
+    snapshots: SnapshotBuffer,
+    interp: Interpolator, // where to draw everyone but me
+    anims: Animations,
+    net_stats: NetStats,
+    hud: Option<(Instant, Vec<graphics::Text>)>, // Some while shown, with when its text was built
+    chat_log: ChatLog<graphics::Text>,
+    chat_input: Option<String>, // Some while typing
+    chat_shown: usize, // lines of chat on screen, to notice when one disappears
 }
 impl ClientState {
 	fn translate(&self, coord: Coord2D) -> Point2 {
+		let bounds = self.game_state.bounds();
+		Point2::new(
+			(self.screen_dims[0] * coord.x as u32 / bounds.width as u32) as f32,
+			(self.screen_dims[1] * coord.y as u32 / bounds.height as u32) as f32,
+		)
+	}
+
+	// like translate, for positions between cells
+	fn translate_f32(&self, (x, y): (f32, f32)) -> Point2 {
+		let bounds = self.game_state.bounds();
 		Point2::new(
-			(self.screen_dims[0] * coord.x as u32 / GameState::WIDTH as u32) as f32,
-			(self.screen_dims[1] * coord.y as u32 / GameState::HEIGHT as u32) as f32,
+			self.screen_dims[0] as f32 * x / bounds.width as f32,
+			self.screen_dims[1] as f32 * y / bounds.height as f32,
 		)
 	}
+
+	// the cell under a point on the screen
+	fn cell_at(&self, x: i32, y: i32) -> Option<Coord2D> {
+		if x < 0 || y < 0 {
+			return None;
+		}
+		let bounds = self.game_state.bounds();
+		let coord = Coord2D::new(
+			(x as u32 * bounds.width as u32 / self.screen_dims[0]) as u16,
+			(y as u32 * bounds.height as u32 / self.screen_dims[1]) as u16,
+		);
+		if bounds.contains(coord) { Some(coord) } else { None }
+	}
+
+	fn cell_size(&self) -> (f32, f32) {
+		let bounds = self.game_state.bounds();
+		(
+			(self.screen_dims[0] / bounds.width as u32) as f32,
+			(self.screen_dims[1] / bounds.height as u32) as f32,
+		)
+	}
+
+	// rebuilds the HUD text at most every HUD_REFRESH
+	fn update_hud_text(&mut self, ctx: &mut Context) -> GameResult<()> {
+		let now = Instant::now();
+		match self.hud {
+			None => return Ok(()),
+			Some((built_at, ref lines)) if !lines.is_empty() && now.duration_since(built_at) < HUD_REFRESH => return Ok(()),
+			_ => (),
+		}
+		let ms = |d: Option<Duration>| match d {
+			Some(d) => format!("{:.0}ms", secs_f32(d) * 1000.0),
+			None => "-".to_owned(),
+		};
+		let stats = &self.net_stats;
+		let (msgs_out, bytes_out) = stats.sent_per_second();
+		let (msgs_in, bytes_in) = stats.received_per_second();
+		let contents = [
+			format!("RTT {}  avg {}", ms(stats.rtt()), ms(stats.average_rtt())),
+			format!("jitter {}", match stats.jitter() {
+				Some(j) => format!("{:.1}ms", j * 1000.0),
+				None => "-".to_owned(),
+			}),
+			format!("out {} msg/s {} B/s", msgs_out, bytes_out),
+			format!("in {} msg/s {} B/s", msgs_in, bytes_in),
+			format!("server tick {}", stats.server_tick()),
+		];
+		let font = graphics::Font::default_font()?;
+		let mut lines = vec![];
+		for line in contents.iter() {
+			lines.push(graphics::Text::new(ctx, line, &font)?);
+		}
+		self.hud = Some((now, lines));
+		Ok(())
+	}
+
+	// text top-left, with a bar per recent round trip underneath, newest on the right
+	fn draw_hud(&mut self, ctx: &mut Context) -> GameResult<()> {
+		self.update_hud_text(ctx)?;
+		let lines = match self.hud {
+			Some((_, ref lines)) => lines,
+			None => return Ok(()),
+		};
+		let (cell_w, cell_h) = self.cell_size();
+		let top = HUD_LINE_HEIGHT; // below the scores
+		let text_height = HUD_LINE_HEIGHT * lines.len() as f32;
+		let backdrop = graphics::DrawParam {
+			dest: Point2::new(0.0, top),
+			scale: Point2::new(HUD_WIDTH / cell_w, (text_height + HUD_GRAPH_HEIGHT) / cell_h),
+			.. Default::default()
+		};
+		graphics::set_color(ctx, HUD_BACKDROP_COLOR)?;
+		graphics::draw_ex(ctx, &self.mesh, backdrop)?;
+		graphics::set_color(ctx, (255, 255, 255).into())?;
+		for (i, line) in lines.iter().enumerate() {
+			graphics::draw(ctx, line, Point2::new(4.0, top + HUD_LINE_HEIGHT * i as f32), 0.0)?;
+		}
+		let history = self.net_stats.rtt_history();
+		let to_ms = |d: &Duration| secs_f32(*d) * 1000.0;
+		let scale_ms = history.iter().map(&to_ms).fold(HUD_GRAPH_MIN_SCALE_MS, f32::max);
+		let bar_w = HUD_WIDTH / RTT_HISTORY as f32;
+		let graph_bottom = top + text_height + HUD_GRAPH_HEIGHT;
+		let first_slot = RTT_HISTORY - history.len();
+		graphics::set_color(ctx, HUD_GRAPH_COLOR)?;
+		for (i, rtt) in history.iter().enumerate() {
+			let bar_h = HUD_GRAPH_HEIGHT * to_ms(rtt) / scale_ms;
+			let param = graphics::DrawParam {
+				dest: Point2::new(bar_w * (first_slot + i) as f32, graph_bottom - bar_h),
+				scale: Point2::new(bar_w / cell_w, bar_h / cell_h),
+				.. Default::default()
+			};
+			graphics::draw_ex(ctx, &self.mesh, param)?;
+		}
+		Ok(())
+	}
+
+	// a fading frame per map ping, with who sent it in the corner
+	fn draw_marks(&self, ctx: &mut Context, effects: &[(Effect, f32)]) -> GameResult<()> {
+		let (cell_w, cell_h) = self.cell_size();
+		for &(effect, t) in effects.iter() {
+			if let Effect::Mark { at, from } = effect {
+				let scale = MARK_START_SCALE + (1.0 - MARK_START_SCALE) * t.min(0.5) * 2.0;
+				let screen_point = self.translate(at);
+				let (w, h) = (cell_w * scale, cell_h * scale);
+				let left = screen_point.x - (w - cell_w) / 2.0;
+				let top = screen_point.y - (h - cell_h) / 2.0;
+				let (rim_w, rim_h) = (cell_w * MARK_RIM, cell_h * MARK_RIM);
+				let bars = [
+					(left, top, w, rim_h),
+					(left, top + h - rim_h, w, rim_h),
+					(left, top, rim_w, h),
+					(left + w - rim_w, top, rim_w, h),
+				];
+				let mut color = MARK_COLOR;
+				color.a = 1.0 - t;
+				graphics::set_color(ctx, color)?;
+				for &(x, y, bar_w, bar_h) in bars.iter() {
+					let param = graphics::DrawParam {
+						dest: Point2::new(x, y),
+						scale: Point2::new(bar_w / cell_w, bar_h / cell_h),
+						.. Default::default()
+					};
+					graphics::draw_ex(ctx, &self.mesh, param)?;
+				}
+				if let Some(moniker_text) = self.text_cache.get(&from) {
+					graphics::draw(ctx, moniker_text, Point2::new(left + w, top - HUD_LINE_HEIGHT), 0.0)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// the newest chat lines, with whatever's being typed underneath
+	fn draw_chat(&self, ctx: &mut Context) -> GameResult<()> {
+		let typing = self.chat_input.is_some();
+		let lines = self.chat_log.visible(Instant::now(), typing);
+		if lines.is_empty() && !typing {
+			return Ok(());
+		}
+		let rows = lines.len() + if typing { 1 } else { 0 };
+		let (cell_w, cell_h) = self.cell_size();
+		let height = HUD_LINE_HEIGHT * rows as f32;
+		let top = self.screen_dims[1] as f32 - height;
+		let backdrop = graphics::DrawParam {
+			dest: Point2::new(0.0, top),
+			scale: Point2::new(CHAT_WIDTH / cell_w, height / cell_h),
+			.. Default::default()
+		};
+		graphics::set_color(ctx, HUD_BACKDROP_COLOR)?;
+		graphics::draw_ex(ctx, &self.mesh, backdrop)?;
+		graphics::set_color(ctx, (255, 255, 255).into())?;
+		for (i, line) in lines.iter().enumerate() {
+			graphics::draw(ctx, *line, Point2::new(4.0, top + HUD_LINE_HEIGHT * i as f32), 0.0)?;
+		}
+		if let Some(ref input) = self.chat_input {
+			let prompt = graphics::Text::new(ctx, &format!("> {}_", input), &graphics::Font::default_font()?)?;
+			graphics::draw(ctx, &prompt, Point2::new(4.0, top + HUD_LINE_HEIGHT * lines.len() as f32), 0.0)?;
+		}
+		Ok(())
+	}
+
+	// while typing, keys edit the message instead of moving
+	fn chat_key(&mut self, keycode: Keycode) {
+		match keycode {
+			Keycode::Return | Keycode::KpEnter => {
+				let input = self.chat_input.take().unwrap_or_default();
+				if let Some(text) = chat::clean(&input) {
+					send_counted(&mut self.mm, &mut self.net_stats, & Serverward::Chat(text));
+				}
+			},
+			Keycode::Escape => self.chat_input = None,
+			Keycode::Backspace => {
+				if let Some(ref mut input) = self.chat_input {
+					input.pop();
+				}
+			},
+			_ => return,
+		}
+		self.no_change = false;
+	}
+
+	// rebuilds the score text only when the scores have changed
+	fn update_score_text(&mut self, ctx: &mut Context) -> GameResult<()> {
+		let scores = [
+			self.game_state.team_score(Team::Red),
+			self.game_state.team_score(Team::Blue),
+		];
+		if let Some((cached, _)) = self.score_text {
+			if cached == scores {
+				return Ok(());
+			}
+		}
+		let text = graphics::Text::new(
+			ctx,
+			& format!("RED {}  BLUE {}", scores[0], scores[1]),
+			& graphics::Font::default_font()?,
+		)?;
+		self.score_text = Some((scores, text));
+		Ok(())
+	}
 }
 
 impl event::EventHandler for ClientState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if !timer::check_update_time(ctx, TARGET_FPS) {
+            timer::sleep(IDLE_SLEEP); // not due yet. don't spin
+            return Ok(());
+        }
+        while timer::check_update_time(ctx, TARGET_FPS) {} // skip frames we fell behind on
         self.poll.poll(&mut self.events, self.poll_timeout).expect("poll failed");
+        let now = Instant::now();
+        self.anims.expire(now);
+        self.net_stats.expire(now);
+        let chat_shown = self.chat_log.shown(now, self.chat_input.is_some());
+        if chat_shown != self.chat_shown {
+            self.chat_shown = chat_shown;
+            self.no_change = false;
+        }
+        if let Some(id) = self.net_stats.due_ping(now) {
+            let rtt_micros = self.net_stats.rtt().map(|rtt| (secs_f32(rtt) * 1e6) as u32);
+            send_counted(&mut self.mm, &mut self.net_stats, & Serverward::Ping(id, rtt_micros));
+        }
+        if self.interp.in_motion(now) || self.anims.is_animating(now) || self.hud.is_some() {
+            self.no_change = false;
+        }
         if self.events.is_empty() {
             return Ok(());
         }
         self.no_change = false;
         self.events.clear();
         use self::Clientward::*;
-        let (gs, mm, tx_cache) = (&mut self.game_state, &mut self.mm, &mut self.text_cache); 
-        mm.recv_all_map( |_me, msg| {
-        	println!("got {:?} from server", &msg);
+        let (gs, mm, tx_cache, snapshots, anims, stats, chat_log) = (&mut self.game_state, &mut self.mm,
+                                                                     &mut self.text_cache, &mut self.snapshots,
+                                                                     &mut self.anims, &mut self.net_stats,
+                                                                     &mut self.chat_log);
+        mm.recv_all_packed_map( |me, packed| {
+        	stats.on_received(packed.byte_len(), Instant::now());
+        	let msg = packed.unpack::<Clientward>().expect("Server sent garbage!");
+        	trace!("recv kind={} bytes={}", msg.kind(), packed.byte_len());
             match msg {
                 Welcome(_) => panic!("Not expecting a welcome"),
-                AddPlayer(moniker, coord) => {
+                Refresh(essence) => {
+                	match GameState::from_essence(essence) {
+                		Ok(refreshed) => *gs = refreshed,
+                		Err(e) => {
+                			warn!("refused refresh err={}", e);
+                			return;
+                		},
+                	}
+                	for (&moniker, _obj) in gs.player_iter() {
+                		if !tx_cache.contains_key(&moniker) {
+                			insert_into_cache(ctx, tx_cache, moniker);
+                		}
+                	}
+                },
+                Snapshot(id, base, delta) => {
+                	match snapshots.resolve(id, base, &delta) {
+                		Some(essence) => match GameState::from_essence(essence) {
+                			Ok(resolved) => {
+                				*gs = resolved;
+                				for (&moniker, _obj) in gs.player_iter() {
+                					if !tx_cache.contains_key(&moniker) {
+                						insert_into_cache(ctx, tx_cache, moniker);
+                					}
+                				}
+                				send_counted(me, stats, & Serverward::AckSnapshot(id));
+                			},
+                			Err(e) => warn!("refused snapshot id={} err={}", id, e),
+                		},
+                		None => debug!("snapshot dropped id={} base={} reason=base_gone", id, base),
+                	}
+                },
+                AddPlayer(moniker, player) => {
     				insert_into_cache(ctx, tx_cache, moniker);
-                    gs.try_add_player(moniker, coord);
+                    gs.try_insert_player(moniker, player);
                 },
                 RemovePlayer(moniker) => {
                 	tx_cache.remove(&moniker);
                     gs.try_remove_player(moniker);
                 },
                 UpdMove(moniker, dir) => {
-                    gs.move_moniker_in_dir(moniker, dir);
+                    anims.animate_move(gs, moniker, dir, Instant::now());
+                },
+                EndProtection(moniker) => { gs.end_protection(moniker); },
+                Pong(id, server_tick) => {
+                	stats.on_pong(id, server_tick, Instant::now());
+                	if let Some(rtt) = stats.rtt() {
+                		debug!("pong id={} server_tick={} rtt_ms={:.1}", id, server_tick, secs_f32(rtt) * 1000.0);
+                	}
+                },
+                Mark { from, at } => {
+                	if !tx_cache.contains_key(&from) {
+                		insert_into_cache(ctx, tx_cache, from); // under fog, they may be out of sight
+                	}
+                	anims.mark(from, at, Instant::now());
+                },
+                Chat { from, text } => {
+                	let line = graphics::Font::default_font()
+                		.and_then(|font| graphics::Text::new(ctx, &format!("{}: {}", from.0, text), &font));
+                	if let Ok(line) = line {
+                		chat_log.push(line, Instant::now());
+                	}
                 },
                 some_err => {
-                    println!("Server sent err msg {:?}", some_err);
+                    error!("server sent err kind={}", some_err.kind());
                     panic!("server sent err");
                 },
             };
         }).1.expect("Failed to read from server!");
+        self.interp.record(&self.game_state, Instant::now());
         Ok(())
     }
 
     fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
+    	if self.chat_input.is_some() {
+    		self.chat_key(keycode);
+    		return;
+    	}
+    	if keycode == Keycode::Return {
+    		self.chat_input = Some(String::new());
+    		self.no_change = false;
+    		return;
+    	}
+    	if keycode == Keycode::F3 {
+    		self.hud = match self.hud {
+    			Some(_) => None,
+    			None => Some((Instant::now(), vec![])),
+    		};
+    		self.no_change = false;
+    		return;
+    	}
     	let mut mv = |dir| {
-    		if self.last_move_at.elapsed() < MOVE_PERIOD {
-    			println!("I'm moving too fast!");
+    		let move_period = self.game_state.get_player(self.my_moniker)
+    			.map(move_period_of)
+    			.unwrap_or(MOVE_PERIOD);
+    		if self.last_move_at.elapsed() < move_period {
+    			debug!("too fast moniker={}", self.my_moniker.0);
     			return;
     		}
     		self.last_move_at = Instant::now();
-			self.mm.send(& Serverward::ReqMove(dir))
-            .expect("req fail")
+			send_counted(&mut self.mm, &mut self.net_stats, & Serverward::ReqMove(dir))
     	};
         match keycode {
         	Keycode::A |
@@ -216,39 +663,180 @@ impl event::EventHandler for ClientState {
         }
     }
 
+    // clicking a cell pings it for everyone
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: i32, y: i32) {
+    	if button != MouseButton::Left {
+    		return;
+    	}
+    	if let Some(at) = self.cell_at(x, y) {
+    		send_counted(&mut self.mm, &mut self.net_stats, & Serverward::Mark(at));
+    	}
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, text: String) {
+    	if let Some(ref mut input) = self.chat_input {
+    		let room = CHAT_MAX_CHARS - input.chars().count();
+    		input.extend(text.chars().take(room));
+    		self.no_change = false;
+    	}
+    }
+
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
     	if self.no_change {
     		return Ok(());
     	}
         graphics::clear(ctx);
-    	for (&moniker, player_obj) in self.game_state.player_iter() {
-    		let moniker_text = self.text_cache.get(&moniker).unwrap();
-    		let screen_point = self.translate(player_obj.coord);
+    	for coord in self.game_state.coord_iter() {
+    		if let Some(team) = self.game_state.base_at(coord) {
+    			let param = graphics::DrawParam {
+    				dest: self.translate(coord), .. Default::default()
+    			};
+    			graphics::set_color(ctx, BASE_COLORS[team.index()])?;
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+    	}
+    	let (cell_w, cell_h) = self.cell_size();
+    	for (&coord, &tile) in self.game_state.tile_iter() {
+    		if self.game_state.is_wall_at(coord) {
+    			continue;
+    		}
+    		let screen_point = self.translate(coord);
     		let param = graphics::DrawParam {
     			dest: screen_point, .. Default::default()
     		};
-        	graphics::set_color(ctx, CHARGE_COLORS[player_obj.charge as usize])?;
+    		graphics::set_color(ctx, tile_color(tile))?;
     		graphics::draw_ex(ctx, &self.mesh, param)?;
-        	graphics::set_color(ctx, (0, 0, 0).into())?;
-    		graphics::draw_ex(ctx, moniker_text, param)?;
+    		if let Tile::Conveyor(dir) = tile {
+    			let arrow_param = conveyor_arrow_param(screen_point, (cell_w, cell_h), dir);
+    			graphics::set_color(ctx, CONVEYOR_ARROW_COLOR)?;
+    			graphics::draw_ex(ctx, &self.mesh, arrow_param)?;
+    		}
     	}
-    	graphics::set_color(ctx, (40, 0, 0).into())?;
+    	let now = Instant::now();
+    	let effects = self.anims.active(now);
+    	let sliding_to: Vec<Coord2D> = effects.iter()
+    		.filter_map(|&(effect, _)| match effect {
+    			Effect::WallSlide { to, .. } => Some(to),
+    			_ => None,
+    		})
+    		.collect();
+    	graphics::set_color(ctx, WALL_COLOR)?;
     	for coord in self.game_state.coord_iter()
-    	.filter(|&coord| self.game_state.is_wall_at(coord)) {
+    	.filter(|&coord| self.game_state.is_wall_at(coord) && !sliding_to.contains(&coord)) {
     		let screen_point = self.translate(coord);
     		let param = graphics::DrawParam {
     			dest: screen_point, .. Default::default()
     		};
     		graphics::draw_ex(ctx, &self.mesh, param)?;
     	}
-    	graphics::set_color(ctx, (255, 255, 0).into())?;
-    	for &coord in self.game_state.blob_iter() {
+    	for &(effect, t) in effects.iter() {
+    		if let Effect::WallSlide { from, to } = effect {
+    			let param = graphics::DrawParam {
+    				dest: self.translate_f32(lerp(from, to, t)), .. Default::default()
+    			};
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+    	}
+    	for (&coord, &kind) in self.game_state.blob_iter()
+    	.filter(|&(&coord, _)| self.game_state.player_can_see(self.my_moniker, coord)) {
+    		// under fog, blobs out of sight may have moved on since
     		let screen_point = self.translate(coord);
     		let param = graphics::DrawParam {
     			dest: screen_point, .. Default::default()
     		};
+    		graphics::set_color(ctx, PICKUP_COLORS[kind.index()])?;
     		graphics::draw_ex(ctx, &self.mesh, param)?;
     	}
+    	for &(effect, t) in effects.iter() {
+    		if let Effect::Pickup { at, kind } = effect {
+    			// grows from the cell's centre as it fades
+    			let scale = 1.0 + (PICKUP_BURST_SCALE - 1.0) * t;
+    			let screen_point = self.translate(at);
+    			let param = graphics::DrawParam {
+    				dest: Point2::new(
+    					screen_point.x - cell_w * (scale - 1.0) / 2.0,
+    					screen_point.y - cell_h * (scale - 1.0) / 2.0,
+    				),
+    				scale: Point2::new(scale, scale),
+    				.. Default::default()
+    			};
+    			let mut color = PICKUP_COLORS[kind.index()];
+    			color.a = 1.0 - t;
+    			graphics::set_color(ctx, color)?;
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+    	}
+    	let inset = (1.0 - CHARGE_INSET_SCALE) / 2.0;
+    	for (&moniker, player_obj) in self.game_state.player_iter() {
+    		let moniker_text = self.text_cache.get(&moniker).unwrap();
+    		// my own square steps straight to where I am. the rest trail behind, smoothly
+    		let position = if moniker == self.my_moniker {
+    			self.anims.my_position(&self.game_state, now)
+    		} else {
+    			self.interp.position(moniker, now)
+    		};
+    		let screen_point = match position {
+    			Some(pos) => self.translate_f32(pos),
+    			None => self.translate(player_obj.coord),
+    		};
+    		let param = graphics::DrawParam {
+    			dest: screen_point, .. Default::default()
+    		};
+    		let inner_param = graphics::DrawParam {
+    			dest: Point2::new(
+    				screen_point.x + cell_w * inset,
+    				screen_point.y + cell_h * inset,
+    			),
+    			scale: Point2::new(CHARGE_INSET_SCALE, CHARGE_INSET_SCALE),
+    			.. Default::default()
+    		};
+    		let mut team_color = TEAM_COLORS[player_obj.team.index()];
+    		if player_obj.ghost_moves > 0 {
+    			team_color.a = GHOST_ALPHA;
+    		}
+    		if player_obj.is_protected() {
+    			let rim_inset = (1.0 - PROTECTED_INSET_SCALE) / 2.0;
+    			let team_param = graphics::DrawParam {
+    				dest: Point2::new(
+    					screen_point.x + cell_w * rim_inset,
+    					screen_point.y + cell_h * rim_inset,
+    				),
+    				scale: Point2::new(PROTECTED_INSET_SCALE, PROTECTED_INSET_SCALE),
+    				.. Default::default()
+    			};
+    			graphics::set_color(ctx, PROTECTED_RIM_COLOR)?;
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    			graphics::set_color(ctx, team_color)?;
+    			graphics::draw_ex(ctx, &self.mesh, team_param)?;
+    		} else {
+    			graphics::set_color(ctx, team_color)?;
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+        	graphics::set_color(ctx, CHARGE_COLORS[player_obj.charge as usize])?;
+    		graphics::draw_ex(ctx, &self.mesh, inner_param)?;
+        	graphics::set_color(ctx, (0, 0, 0).into())?;
+    		graphics::draw_ex(ctx, moniker_text, param)?;
+    	}
+    	if self.game_state.fog_radius().is_some() {
+    		graphics::set_color(ctx, FOG_COLOR)?;
+    		for coord in self.game_state.coord_iter()
+    		.filter(|&coord| !self.game_state.player_can_see(self.my_moniker, coord)) {
+    			let param = graphics::DrawParam {
+    				dest: self.translate(coord), .. Default::default()
+    			};
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+    	}
+    	self.draw_marks(ctx, &effects)?;
+    	self.update_score_text(ctx)?;
+    	if let Some((_, ref score_text)) = self.score_text {
+    		graphics::set_color(ctx, (255, 255, 255).into())?;
+    		graphics::draw(ctx, score_text, Point2::new(4.0, 0.0), 0.0)?;
+    	}
+    	if self.hud.is_some() {
+    		self.draw_hud(ctx)?;
+    	}
+    	self.draw_chat(ctx)?;
         graphics::present(ctx);
         self.no_change = true;
         Ok(())
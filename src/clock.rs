@@ -0,0 +1,56 @@
+use std::{
+	fmt,
+	sync::{
+		Arc,
+		Mutex,
+	},
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+/*
+Where the server gets the time from. Real servers use SystemClock; simulations
+use a ManualClock and move it along themselves, so a whole session takes as
+long as its test wants and no longer.
+*/
+pub trait Clock: Send + Sync + fmt::Debug {
+	fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+	#[inline]
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+}
+
+// stands still until told to move. clones share the same time
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+	now: Arc<Mutex<Instant>>,
+}
+impl ManualClock {
+	pub fn new() -> Self {
+		ManualClock { now: Arc::new(Mutex::new(Instant::now())) }
+	}
+
+	pub fn advance(&self, by: Duration) {
+		*self.now.lock().unwrap() += by;
+	}
+}
+impl Default for ManualClock {
+	fn default() -> Self {
+		ManualClock::new()
+	}
+}
+impl Clock for ManualClock {
+	fn now(&self) -> Instant {
+		*self.now.lock().unwrap()
+	}
+}
@@ -1,6 +1,14 @@
 
 use ::game::*;
-use ::middleman;
+use ::snapshot::{
+	SnapshotId,
+	SnapshotBuffer,
+	EssenceDelta,
+};
+use ::middleman::{
+	self,
+	Middleman,
+};
 use ::std::{self,
 	time::Duration,
 };
@@ -9,35 +17,131 @@ use super::mio;
 /////////////////////////////////////////////////////////////
 
 pub const MOVE_PERIOD: Duration = Duration::from_millis(130);
+pub const BOOSTED_MOVE_PERIOD: Duration = Duration::from_millis(65);
 pub const BOT_MOVE_PERIOD: Duration = Duration::from_millis(400);
+pub const BOT_WANDER_ONE_IN: u32 = 4; // bots take a random step this often
+pub const SPAWN_PROTECTION_TIME: Duration = Duration::from_secs(2); // unless the moves run out first
+pub const MARK_PERIOD: Duration = Duration::from_secs(1); // the least time between one player's map pings
+pub const MARK_SHOW_TIME: Duration = Duration::from_secs(3); // how long clients show a map ping
 pub const DIR_CHOICES: [Direction; 4] = [
 	Direction::Up, Direction::Down, Direction::Left, Direction::Right
 ];
 
+#[inline]
+pub fn secs_f32(d: Duration) -> f32 {
+	d.as_secs() as f32 + d.subsec_nanos() as f32 * 1e-9
+}
+
+pub fn move_period_of(player: &PlayerObject) -> Duration {
+	if player.speed_moves > 0 {
+		BOOSTED_MOVE_PERIOD
+	} else {
+		MOVE_PERIOD
+	}
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Clientward {
 	Welcome(GameStateEssence),
-	AddPlayer(Moniker, Coord2D),
-	RemovePlayer(Moniker),
+	Refresh(GameStateEssence), // resyncs a client under fog that fell out of step
+	Snapshot(SnapshotId, SnapshotId, EssenceDelta), // id, and the id it's a delta against
+	AddPlayer(Moniker, PlayerObject), // joined, or came into view under fog
+	RemovePlayer(Moniker), // left, or went out of view under fog
 	UpdMove(Moniker, Direction),
+	EndProtection(Moniker), // their spawn protection ran out of time
+	Pong(u32, u64), // the ping's id, and the server's tick
+	Chat { from: Moniker, text: String },
+	Mark { from: Moniker, at: Coord2D }, // someone pinged a cell: look here
 	ErrorTakenMoniker,
+	ErrorGameFull, // nowhere left to spawn
 	ErrorIllegalMove,
 	ErrorSocketDead,
 	ErrorExpectedReq,
 	ErrorExpectedHello,
 }
 impl middleman::Message for Clientward {}
+impl Clientward {
+	pub const KINDS: [&'static str; 16] = [
+		"Welcome", "Refresh", "Snapshot", "AddPlayer", "RemovePlayer", "UpdMove", "EndProtection",
+		"Pong", "Chat", "Mark", "ErrorTakenMoniker", "ErrorGameFull", "ErrorIllegalMove",
+		"ErrorSocketDead", "ErrorExpectedReq", "ErrorExpectedHello",
+	];
+
+	// the variant's name, for logs and metrics. whole messages can be huge
+	pub fn kind(&self) -> &'static str {
+		use self::Clientward::*;
+		match *self {
+			Welcome(_) => "Welcome",
+			Refresh(_) => "Refresh",
+			Snapshot(..) => "Snapshot",
+			AddPlayer(..) => "AddPlayer",
+			RemovePlayer(_) => "RemovePlayer",
+			UpdMove(..) => "UpdMove",
+			EndProtection(_) => "EndProtection",
+			Pong(..) => "Pong",
+			Chat { .. } => "Chat",
+			Mark { .. } => "Mark",
+			ErrorTakenMoniker => "ErrorTakenMoniker",
+			ErrorGameFull => "ErrorGameFull",
+			ErrorIllegalMove => "ErrorIllegalMove",
+			ErrorSocketDead => "ErrorSocketDead",
+			ErrorExpectedReq => "ErrorExpectedReq",
+			ErrorExpectedHello => "ErrorExpectedHello",
+		}
+	}
+}
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Serverward {
-	Hello(Moniker),
+	Hello(Moniker, Option<Team>),
 	ReqMove(Direction),
+	AckSnapshot(SnapshotId),
+	Ping(u32, Option<u32>), // an id, and the client's latest round trip in microseconds
+	Chat(String),
+	Mark(Coord2D), // a map ping, for everyone to see
 }
 impl middleman::Message for Serverward {}
+impl Serverward {
+	pub const KINDS: [&'static str; 6] = [
+		"Hello", "ReqMove", "AckSnapshot", "Ping", "Chat", "Mark",
+	];
+
+	pub fn kind(&self) -> &'static str {
+		use self::Serverward::*;
+		match *self {
+			Hello(..) => "Hello",
+			ReqMove(_) => "ReqMove",
+			AckSnapshot(_) => "AckSnapshot",
+			Ping(..) => "Ping",
+			Chat(_) => "Chat",
+			Mark(_) => "Mark",
+		}
+	}
+}
 
 /////////////////////////////////////////////////////////////
 
+// sends Hello and waits for the Welcome. every client starts like this.
+// None if the server turned us away, or welcomed us into a game we can't build
+pub fn client_handshake(mm: &mut Middleman, poll: &mio::Poll, events: &mut mio::Events,
+	                    my_moniker: Moniker, my_team: Option<Team>) -> Option<(SnapshotBuffer, GameState)> {
+	mm.send(& Serverward::Hello(my_moniker, my_team)).expect("HELLO send fail");
+	match mm.recv_blocking_solo::<Clientward>(poll, events, None).expect("crash").unwrap() {
+		Clientward::Welcome(essence) => match GameState::from_essence(essence.clone()) {
+			Ok(game_state) => Some((SnapshotBuffer::new(essence), game_state)),
+			Err(e) => {
+				error!("refused welcome moniker={} err={}", my_moniker.0, e);
+				None
+			},
+		},
+		msg => {
+			error!("handshake failed moniker={} kind={}", my_moniker.0, msg.kind());
+			None
+		},
+	}
+}
+
 pub type MioStream = mio::net::TcpStream;
 pub type StdStream = std::net::TcpStream;
 pub type MioListener = mio::net::TcpListener;
\ No newline at end of file
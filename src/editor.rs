@@ -0,0 +1,459 @@
+use ::game::*;
+use ::mapfile::MapFile;
+use ::client::{
+	build_square_mesh,
+	tile_color,
+	conveyor_arrow_param,
+	BASE_COLORS,
+	PICKUP_COLORS,
+	WALL_COLOR,
+	CONVEYOR_ARROW_COLOR,
+};
+
+use std::path::Path;
+
+use ggez::{
+    Context,
+    GameResult,
+    conf,
+    graphics::{
+        self,
+        Color,
+        Point2,
+        Mesh,
+    },
+    event::{
+        self,
+        Keycode,
+        Mod,
+        MouseButton,
+        MouseState,
+        LCTRLMOD,
+        RCTRLMOD,
+        LSHIFTMOD,
+        RSHIFTMOD,
+    },
+};
+
+const SPAWN_COLOR: Color = Color { r:1.0, g:1.0, b:1.0, a:1. };
+const PENDING_PORTAL_ALPHA: f32 = 0.4;
+
+// fraction of a cell the spawn and blob spawn markers occupy
+const SPAWN_MARKER_SCALE: f32 = 0.5;
+const BLOB_MARKER_SCALE: f32 = 0.3;
+
+// oldest edits are forgotten beyond this
+const UNDO_LIMIT: usize = 200;
+
+const CONTROLS: &str = "\
+LMB paint, RMB erase.
+1 wall, 2 spawn, 3 blob spawn, 4 portal (click both ends), 5 conveyor (again to turn), 6 ice, 7 pit.
+Ctrl+Z undo, Ctrl+Y redo, Ctrl+S save, Ctrl+L reload from file, Escape quit.";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Brush {
+	Wall,
+	Spawn,
+	BlobSpawn,
+	Portal,
+	Conveyor(Direction),
+	Ice,
+	Pit,
+}
+impl Brush {
+	fn name(self) -> &'static str {
+		match self {
+			Brush::Wall => "wall",
+			Brush::Spawn => "spawn",
+			Brush::BlobSpawn => "blob spawn",
+			Brush::Portal => "portal",
+			Brush::Conveyor(Direction::Up) => "conveyor ^",
+			Brush::Conveyor(Direction::Down) => "conveyor v",
+			Brush::Conveyor(Direction::Left) => "conveyor <",
+			Brush::Conveyor(Direction::Right) => "conveyor >",
+			Brush::Ice => "ice",
+			Brush::Pit => "pit",
+		}
+	}
+}
+
+fn turn_clockwise(dir: Direction) -> Direction {
+	match dir {
+		Direction::Up => Direction::Right,
+		Direction::Right => Direction::Down,
+		Direction::Down => Direction::Left,
+		Direction::Left => Direction::Up,
+	}
+}
+
+pub fn editor_enter(path: &str) {
+	let map = if Path::new(path).exists() {
+		match MapFile::read(path) {
+			Ok(map) => map,
+			Err(e) => {
+				error!("couldn't open map path={} err={}", path, e);
+				return;
+			},
+		}
+	} else {
+		println!("`{}` doesn't exist yet. Starting a new map.", path);
+		MapFile::empty(Bounds::DEFAULT.width, Bounds::DEFAULT.height)
+	};
+	println!("{}", CONTROLS);
+
+	let c = conf::Conf::new();
+    let ctx = &mut Context::load_from_conf("pinggame_editor", "ggez", c).unwrap();
+    let (w, h) = graphics::get_size(ctx);
+    let mesh = build_square_mesh(ctx, map.bounds()).unwrap();
+    let mut es = EditorState {
+    	path: path.to_owned(),
+    	saved_map: map.clone(),
+    	map,
+    	screen_dims: [w, h],
+    	mesh,
+    	brush: Brush::Wall,
+    	painting: None,
+    	pending_portal: None,
+    	undo: vec![],
+    	redo: vec![],
+    	quit_armed: false,
+    	no_change: false,
+    	status_text: None,
+    };
+    event::run(ctx, &mut es).unwrap();
+}
+
+struct EditorState {
+	path: String,
+	map: MapFile,
+	saved_map: MapFile, // as it is on disk. for spotting unsaved changes
+	screen_dims: [u32; 2],
+	mesh: Mesh,
+	brush: Brush,
+	painting: Option<MouseButton>,
+	pending_portal: Option<Coord2D>, // first end of a portal pair being placed
+	undo: Vec<MapFile>,
+	redo: Vec<MapFile>,
+	quit_armed: bool,
+	no_change: bool,
+	status_text: Option<(String, graphics::Text)>,
+}
+impl EditorState {
+	fn translate(&self, coord: Coord2D) -> Point2 {
+		Point2::new(
+			(self.screen_dims[0] * coord.x as u32 / self.map.width as u32) as f32,
+			(self.screen_dims[1] * coord.y as u32 / self.map.height as u32) as f32,
+		)
+	}
+
+	fn cell_size(&self) -> (f32, f32) {
+		(
+			(self.screen_dims[0] / self.map.width as u32) as f32,
+			(self.screen_dims[1] / self.map.height as u32) as f32,
+		)
+	}
+
+	fn coord_at(&self, x: i32, y: i32) -> Option<Coord2D> {
+		if x < 0 || y < 0 {
+			return None;
+		}
+		let coord = Coord2D::new(
+			(x as u32 * self.map.width as u32 / self.screen_dims[0]) as u16,
+			(y as u32 * self.map.height as u32 / self.screen_dims[1]) as u16,
+		);
+		if self.map.bounds().contains(coord) {
+			Some(coord)
+		} else {
+			None
+		}
+	}
+
+	fn dirty(&self) -> bool {
+		self.map != self.saved_map
+	}
+
+	fn remember(&mut self) {
+		self.undo.push(self.map.clone());
+		if self.undo.len() > UNDO_LIMIT {
+			self.undo.remove(0);
+		}
+		self.redo.clear();
+	}
+
+	fn undo(&mut self) {
+		if let Some(map) = self.undo.pop() {
+			let current = ::std::mem::replace(&mut self.map, map);
+			self.redo.push(current);
+			self.pending_portal = None;
+		}
+	}
+
+	fn redo(&mut self) {
+		if let Some(map) = self.redo.pop() {
+			let current = ::std::mem::replace(&mut self.map, map);
+			self.undo.push(current);
+			self.pending_portal = None;
+		}
+	}
+
+	fn apply(&mut self, button: MouseButton, coord: Coord2D) {
+		if button == MouseButton::Right {
+			self.map.clear_cell(coord);
+			return;
+		}
+		if button != MouseButton::Left {
+			return;
+		}
+		match self.brush {
+			Brush::Portal => self.place_portal_end(coord),
+			Brush::Wall => {
+				self.map.clear_cell(coord);
+				self.map.walls.push(coord);
+			},
+			Brush::Spawn => {
+				self.map.clear_cell(coord);
+				self.map.spawn_points.push(coord);
+			},
+			Brush::BlobSpawn => {
+				self.map.clear_cell(coord);
+				self.map.blob_spawns.push(coord);
+			},
+			Brush::Conveyor(dir) => {
+				self.map.clear_cell(coord);
+				self.map.tiles.push((coord, Tile::Conveyor(dir)));
+			},
+			Brush::Ice => {
+				self.map.clear_cell(coord);
+				self.map.tiles.push((coord, Tile::Ice));
+			},
+			Brush::Pit => {
+				self.map.clear_cell(coord);
+				self.map.tiles.push((coord, Tile::Pit));
+			},
+		}
+	}
+
+	fn place_portal_end(&mut self, coord: Coord2D) {
+		match self.pending_portal {
+			None => {
+				if self.map.portal_pairs() >= 9 {
+					warn!("at most 9 portal pairs fit in a map file");
+					return;
+				}
+				self.pending_portal = Some(coord);
+			},
+			Some(first) if first == coord => (),
+			Some(first) => {
+				self.pending_portal = None;
+				self.map.clear_cell(first);
+				self.map.clear_cell(coord);
+				self.map.tiles.push((first, Tile::Portal(coord)));
+				self.map.tiles.push((coord, Tile::Portal(first)));
+			},
+		}
+	}
+
+	fn save(&mut self) {
+		if let Err(e) = self.map.save(&self.path) {
+			error!("couldn't save map path={} err={}", self.path, e);
+			return;
+		}
+		self.saved_map = self.map.clone();
+		match self.map.validate() {
+			Ok(()) => println!("Saved `{}`.", self.path),
+			Err(e) => warn!("saved a map the server won't load yet path={} err={}", self.path, e),
+		}
+	}
+
+	fn reload(&mut self, ctx: &mut Context) {
+		match MapFile::read(&self.path) {
+			Ok(map) => {
+				self.remember();
+				self.mesh = build_square_mesh(ctx, map.bounds()).unwrap();
+				self.saved_map = map.clone();
+				self.map = map;
+				self.pending_portal = None;
+				println!("Reloaded `{}`.", self.path);
+			},
+			Err(e) => error!("couldn't reload map path={} err={}", self.path, e),
+		}
+	}
+
+	// rebuilds the status text only when it has changed
+	fn update_status_text(&mut self, ctx: &mut Context) -> GameResult<()> {
+		let status = format!(
+			"[{}] {}{}",
+			self.brush.name(),
+			self.path,
+			if self.dirty() { " *" } else { "" },
+		);
+		if let Some((ref cached, _)) = self.status_text {
+			if cached == &status {
+				return Ok(());
+			}
+		}
+		let text = graphics::Text::new(ctx, &status, &graphics::Font::default_font()?)?;
+		self.status_text = Some((status, text));
+		Ok(())
+	}
+
+	fn draw_marker(&self, ctx: &mut Context, coord: Coord2D, scale: f32) -> GameResult<()> {
+		let (cell_w, cell_h) = self.cell_size();
+		let screen_point = self.translate(coord);
+		let inset = (1.0 - scale) / 2.0;
+		let param = graphics::DrawParam {
+			dest: Point2::new(
+				screen_point.x + cell_w * inset,
+				screen_point.y + cell_h * inset,
+			),
+			scale: Point2::new(scale, scale),
+			.. Default::default()
+		};
+		graphics::draw_ex(ctx, &self.mesh, param)
+	}
+}
+
+impl event::EventHandler for EditorState {
+    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+        Ok(())
+    }
+
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: i32, y: i32) {
+    	if let Some(coord) = self.coord_at(x, y) {
+    		// one undo step per stroke
+    		self.remember();
+    		self.painting = Some(button);
+    		self.apply(button, coord);
+    		self.no_change = false;
+    	}
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: i32, _y: i32) {
+    	if self.painting != Some(button) {
+    		return;
+    	}
+    	self.painting = None;
+    	if self.undo.last() == Some(&self.map) {
+    		// the stroke didn't change anything
+    		self.undo.pop();
+    	}
+    }
+
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, _state: MouseState, x: i32, y: i32, _xrel: i32, _yrel: i32) {
+    	if self.brush == Brush::Portal {
+    		return;
+    	}
+    	if let (Some(button), Some(coord)) = (self.painting, self.coord_at(x, y)) {
+    		self.apply(button, coord);
+    		self.no_change = false;
+    	}
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, keymod: Mod, _repeat: bool) {
+    	let ctrl = keymod.intersects(LCTRLMOD | RCTRLMOD);
+    	let shift = keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+    	if keycode != Keycode::Escape {
+    		self.quit_armed = false;
+    	}
+    	self.no_change = false;
+    	if ctrl {
+    		match keycode {
+    			Keycode::Z if shift => self.redo(),
+    			Keycode::Z => self.undo(),
+    			Keycode::Y => self.redo(),
+    			Keycode::S => self.save(),
+    			Keycode::L => self.reload(ctx),
+    			_ => (),
+    		}
+    		return;
+    	}
+    	let brush = match keycode {
+    		Keycode::Num1 => Brush::Wall,
+    		Keycode::Num2 => Brush::Spawn,
+    		Keycode::Num3 => Brush::BlobSpawn,
+    		Keycode::Num4 => Brush::Portal,
+    		Keycode::Num5 => match self.brush {
+    			Brush::Conveyor(dir) => Brush::Conveyor(turn_clockwise(dir)),
+    			_ => Brush::Conveyor(Direction::Right),
+    		},
+    		Keycode::Num6 => Brush::Ice,
+    		Keycode::Num7 => Brush::Pit,
+    		Keycode::Escape => {
+    			if self.dirty() && !self.quit_armed {
+    				warn!("unsaved changes. press Escape again to quit anyway");
+    				self.quit_armed = true;
+    			} else {
+    				ctx.quit().unwrap();
+    			}
+    			return;
+    		},
+    		_ => return,
+    	};
+    	if brush != Brush::Portal {
+    		self.pending_portal = None;
+    	}
+    	self.brush = brush;
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+    	if self.no_change {
+    		return Ok(());
+    	}
+        graphics::clear(ctx);
+        let bounds = self.map.bounds();
+        for coord in bounds.coord_iter() {
+    		if let Some(team) = bounds.base_at(coord) {
+    			let param = graphics::DrawParam {
+    				dest: self.translate(coord), .. Default::default()
+    			};
+    			graphics::set_color(ctx, BASE_COLORS[team.index()])?;
+    			graphics::draw_ex(ctx, &self.mesh, param)?;
+    		}
+        }
+    	let cell_size = self.cell_size();
+    	for &(coord, tile) in self.map.tiles.iter() {
+    		let screen_point = self.translate(coord);
+    		let param = graphics::DrawParam {
+    			dest: screen_point, .. Default::default()
+    		};
+    		graphics::set_color(ctx, tile_color(tile))?;
+    		graphics::draw_ex(ctx, &self.mesh, param)?;
+    		if let Tile::Conveyor(dir) = tile {
+    			graphics::set_color(ctx, CONVEYOR_ARROW_COLOR)?;
+    			graphics::draw_ex(ctx, &self.mesh, conveyor_arrow_param(screen_point, cell_size, dir))?;
+    		}
+    	}
+    	if let Some(coord) = self.pending_portal {
+    		let mut color = tile_color(Tile::Portal(coord));
+    		color.a = PENDING_PORTAL_ALPHA;
+    		let param = graphics::DrawParam {
+    			dest: self.translate(coord), .. Default::default()
+    		};
+    		graphics::set_color(ctx, color)?;
+    		graphics::draw_ex(ctx, &self.mesh, param)?;
+    	}
+    	graphics::set_color(ctx, WALL_COLOR)?;
+    	for &coord in self.map.walls.iter() {
+    		let param = graphics::DrawParam {
+    			dest: self.translate(coord), .. Default::default()
+    		};
+    		graphics::draw_ex(ctx, &self.mesh, param)?;
+    	}
+    	graphics::set_color(ctx, SPAWN_COLOR)?;
+    	for &coord in self.map.spawn_points.iter() {
+    		self.draw_marker(ctx, coord, SPAWN_MARKER_SCALE)?;
+    	}
+    	graphics::set_color(ctx, PICKUP_COLORS[PickupKind::Charge.index()])?;
+    	for &coord in self.map.blob_spawns.iter() {
+    		self.draw_marker(ctx, coord, BLOB_MARKER_SCALE)?;
+    	}
+    	self.update_status_text(ctx)?;
+    	if let Some((_, ref status_text)) = self.status_text {
+    		graphics::set_color(ctx, (255, 255, 255).into())?;
+    		graphics::draw(ctx, status_text, Point2::new(4.0, 0.0), 0.0)?;
+    	}
+        graphics::present(ctx);
+        self.no_change = true;
+        Ok(())
+    }
+}
@@ -4,18 +4,28 @@ use rand::{
 	SeedableRng,
 	XorShiftRng,
 };
-use bitset::BitSet;
+use grid::WallFlips;
+use mapgen::{
+	self,
+	MapGen,
+	WallGrid,
+};
+use mapfile::{
+	MapError,
+	MapFile,
+};
+use snapshot::EssenceDelta;
 use std::{
 	fmt,
+	str::FromStr,
 	collections::{
 		HashMap,
-		HashSet,
 	},
 };
 
 
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LCGenerator {
 	seed: u64,
 }
@@ -34,12 +44,12 @@ impl LCGenerator { // Linear Congruential Generator
 impl Rng for LCGenerator {
     #[inline]
     fn next_u32(&mut self) -> u32 {
-        self.seed = (Self::A * self.seed + Self::C) % Self::M;
-  		return self.seed as u32;
+        self.seed = Self::A.wrapping_mul(self.seed).wrapping_add(Self::C) % Self::M;
+  		self.seed as u32
     }
     fn next_u64(&mut self) -> u64 {
-        self.seed = (Self::A * self.seed + Self::C) % Self::M;
-  		return self.seed;
+        self.seed = Self::A.wrapping_mul(self.seed).wrapping_add(Self::C) % Self::M;
+  		self.seed
     }
 }
 
@@ -49,33 +59,219 @@ impl Rng for LCGenerator {
 pub struct Moniker(pub char);
 
 pub type ValidMove = bool;
-type GameStateSeed = [u32; 4];
+pub type GameStateSeed = [u32; 4];
 
 fn new_random_seed() -> GameStateSeed {
 	thread_rng().gen()
 }
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug, Hash)]
+pub enum Team {
+	Red, Blue,
+}
+impl Team {
+	pub const ALL: [Team; 2] = [Team::Red, Team::Blue];
+
+	#[inline]
+	pub fn index(self) -> usize {
+		match self {
+			Team::Red => 0,
+			Team::Blue => 1,
+		}
+	}
+}
+impl FromStr for Team {
+	type Err = ();
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_ref() {
+			"red" | "r" => Ok(Team::Red),
+			"blue" | "b" => Ok(Team::Blue),
+			_ => Err(()),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug, Hash)]
+pub enum PickupKind {
+	Charge,      // +1 charge
+	Speed,       // shorter move period for a while
+	Ghost,       // walk through walls for a while
+	Teleport,    // jump to a random free spot
+	WallSpawner, // walls appear at random free spots
+}
+impl PickupKind {
+	#[inline]
+	pub fn index(self) -> usize {
+		match self {
+			PickupKind::Charge => 0,
+			PickupKind::Speed => 1,
+			PickupKind::Ghost => 2,
+			PickupKind::Teleport => 3,
+			PickupKind::WallSpawner => 4,
+		}
+	}
+
+	// charge is the most common. the rest are treats
+	fn random<R: Rng>(rng: &mut R) -> Self {
+		match rng.gen_range(0, 10) {
+			0..=3 => PickupKind::Charge,
+			4 | 5 => PickupKind::Speed,
+			6 | 7 => PickupKind::Ghost,
+			8 => PickupKind::Teleport,
+			_ => PickupKind::WallSpawner,
+		}
+	}
+}
+
+// how the server picks where a newcomer appears
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum SpawnPolicy {
+	Random,     // any free spawn spot
+	#[default]
+	Farthest,   // the spawn spot farthest from everyone else
+	RoundRobin, // the map's spawn points in turn
+	TeamBase,   // inside the newcomer's own base
+}
+impl SpawnPolicy {
+	pub const NAMES: [&'static str; 4] = ["random", "farthest", "roundrobin", "base"];
+}
+impl FromStr for SpawnPolicy {
+	type Err = ();
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_ref() {
+			"random" => Ok(SpawnPolicy::Random),
+			"farthest" => Ok(SpawnPolicy::Farthest),
+			"roundrobin" | "round-robin" => Ok(SpawnPolicy::RoundRobin),
+			"base" | "teambase" => Ok(SpawnPolicy::TeamBase),
+			_ => Err(()),
+		}
+	}
+}
+
+// special floor tiles. part of the map, like the walls
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub enum Tile {
+	Portal(Coord2D),     // step on to appear on the partner portal
+	Conveyor(Direction), // carries you along. can't be entered against the flow
+	Ice,                 // keep sliding until something stops you
+	Pit,                 // fall in and respawn somewhere random
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PlayerObject {
 	pub coord: Coord2D,
 	pub charge: u16,
+	pub team: Team,
+	pub speed_moves: u16, // moves left with a shortened move period
+	pub ghost_moves: u16, // moves left passing through walls
+	pub protected_moves: u16, // moves left of spawn protection
 }
 impl PlayerObject {
 	const POWER_LIMIT: u16 = 3;
+	pub const SPEED_MOVES: u16 = 12;
+	pub const GHOST_MOVES: u16 = 6;
+	pub const WALL_SPAWNER_WALLS: u8 = 4;
+	const TELEPORT_ATTEMPTS: u8 = 8;
+	pub const SPAWN_PROTECTION_MOVES: u16 = 5;
+
+	#[inline]
+	pub fn is_protected(&self) -> bool {
+		self.protected_moves > 0
+	}
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// where the walls and tiles come from before anyone starts pushing them around
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MapSource {
+	Generated {
+		seed: GameStateSeed,
+		gen: MapGen,
+		bounds: Bounds,
+	},
+	Authored(MapFile),
+}
+impl MapSource {
+	// essences come off the wire, and a game can only be built on a map that fits
+	pub fn validate(&self) -> Result<(), MapError> {
+		match *self {
+			MapSource::Generated { ref gen, bounds, .. } => {
+				MapFile::check_size(bounds)?;
+				gen.validate()
+			},
+			MapSource::Authored(ref map) => map.validate_shape(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct GameStateEssence { //everything that CANNOT be generated
 	players: HashMap<Moniker, PlayerObject>, 
-	wall_default_seed: GameStateSeed,
-	wall_override: HashMap<Coord2D, bool>,
-	power_blobs: HashSet<Coord2D>,
+	map: MapSource,
+	wall_flips: WallFlips, // cells whose wall differs from the map's layout
+	power_blobs: HashMap<Coord2D, PickupKind>,
+	team_scores: [u32; 2],
+	fog_radius: Option<u16>,
 	sync_rng: LCGenerator,
 }
+impl GameStateEssence {
+	// what changed going from `base` to `self`. both must be of the same game
+	pub fn delta_from(&self, base: &GameStateEssence) -> EssenceDelta {
+		EssenceDelta {
+			players: self.players.iter()
+				.filter(|&(m, p)| base.players.get(m) != Some(p))
+				.map(|(&m, p)| (m, p.clone()))
+				.collect(),
+			removed_players: base.players.keys()
+				.filter(|m| !self.players.contains_key(m))
+				.cloned()
+				.collect(),
+			wall_flips: self.wall_flips.symmetric_difference(&base.wall_flips),
+			power_blobs: self.power_blobs.iter()
+				.filter(|&(c, k)| base.power_blobs.get(c) != Some(k))
+				.map(|(&c, &k)| (c, k))
+				.collect(),
+			removed_blobs: base.power_blobs.keys()
+				.filter(|c| !self.power_blobs.contains_key(c))
+				.cloned()
+				.collect(),
+			team_scores: if self.team_scores != base.team_scores { Some(self.team_scores) } else { None },
+			sync_rng: if self.sync_rng != base.sync_rng { Some(self.sync_rng) } else { None },
+		}
+	}
+
+	pub fn apply_delta(&mut self, delta: &EssenceDelta) {
+		for moniker in delta.removed_players.iter() {
+			self.players.remove(moniker);
+		}
+		for &(moniker, ref player) in delta.players.iter() {
+			self.players.insert(moniker, player.clone());
+		}
+		self.wall_flips = self.wall_flips.symmetric_difference(&delta.wall_flips);
+		for coord in delta.removed_blobs.iter() {
+			self.power_blobs.remove(coord);
+		}
+		for &(coord, kind) in delta.power_blobs.iter() {
+			self.power_blobs.insert(coord, kind);
+		}
+		if let Some(scores) = delta.team_scores {
+			self.team_scores = scores;
+		}
+		if let Some(rng) = delta.sync_rng {
+			self.sync_rng = rng;
+		}
+	}
+}
+
 pub struct GameState { //all but `essence` can be generated from `essence`
 	essence: GameStateEssence,
-	wall_default: Vec<BitSet>,
+	bounds: Bounds,
+	walls: WallGrid, // the layout with wall_flips applied
+	tiles: HashMap<Coord2D, Tile>,
+	spawn_points: Vec<Coord2D>,
+	blob_spawns: Vec<Coord2D>,
 	non_wall_spaces: usize,
+	occupancy: Vec<Option<Moniker>>, // who stands where, by Bounds::index_of. mirrors essence.players
+	next_spawn_point: usize, // for SpawnPolicy::RoundRobin. server only
 }
 
 impl fmt::Debug for GameState {
@@ -86,17 +282,33 @@ impl fmt::Debug for GameState {
 
 
 impl GameState { // basic stuff
-	pub const WIDTH: u16 = 30;
-	pub const HEIGHT: u16 = 22;
-	pub const TOTAL_COORDS: usize =
-		Self::WIDTH as usize * Self::HEIGHT as usize;
 	pub const NUM_POWER_BLOBS: u8 = 3;
+	pub const NUM_PORTAL_PAIRS: u8 = 2;
+	pub const NUM_CONVEYORS: u8 = 6;
+	pub const NUM_ICE_PATCHES: u8 = 3;
+	pub const NUM_PITS: u8 = 2;
+	// free-spot searches guess at random while at least LIST_SELECT_BELOW of the
+	// cells are empty, pick from the list of empty cells when fewer are, and
+	// give up below GIVE_UP_BELOW
+	const GIVE_UP_BELOW: f32 = 0.04;
+	const LIST_SELECT_BELOW: f32 = 0.4;
+	const SPOT_ATTEMPTS: u8 = 32; // guesses before falling back to the list
 
 	#[inline]
 	pub fn get_essence(& self) -> &GameStateEssence {
 		& self.essence
 	}
 
+	#[inline]
+	pub fn bounds(&self) -> Bounds {
+		self.bounds
+	}
+
+	#[inline]
+	pub fn total_coords(&self) -> usize {
+		self.bounds.total_coords()
+	}
+
 	#[inline]
 	pub fn num_players(&self) -> usize {
 		self.essence.players.len()
@@ -104,7 +316,11 @@ impl GameState { // basic stuff
 
 	#[inline]
 	pub fn num_empty_coords(&self) -> usize {
-		self.non_wall_spaces - self.num_players()
+		// ghosts can stand in walls, which non_wall_spaces already leaves out
+		let players_off_walls = self.essence.players.values()
+			.filter(|p| !self.is_wall_at(p.coord))
+			.count();
+		self.non_wall_spaces - players_off_walls - self.essence.power_blobs.len()
 	}
 
 	#[inline]
@@ -112,13 +328,65 @@ impl GameState { // basic stuff
 		self.essence.players.contains_key(&moniker)
 	}
 
+	#[inline]
+	pub fn get_player(&self, moniker: Moniker) -> Option<&PlayerObject> {
+		self.essence.players.get(&moniker)
+	}
+
+	#[inline]
+	pub fn team_score(&self, team: Team) -> u32 {
+		self.essence.team_scores[team.index()]
+	}
+
+	pub fn team_size(&self, team: Team) -> usize {
+		self.essence.players.values()
+		.filter(|p| p.team == team)
+		.count()
+	}
+
+	// the team with the fewest members. ties go to the earlier team
+	pub fn smallest_team(&self) -> Team {
+		*Team::ALL.iter()
+		.min_by_key(|&&team| self.team_size(team))
+		.unwrap()
+	}
+
 	fn get_player_at(&self, coord: Coord2D) -> Option<&PlayerObject> {
-		for player in self.essence.players.values() {
-			if player.coord == coord {
-				return Some(player);
-			}
+		self.moniker_at(coord)
+		.and_then(|moniker| self.essence.players.get(&moniker))
+	}
+
+	#[inline]
+	fn moniker_at(&self, coord: Coord2D) -> Option<Moniker> {
+		self.occupancy[self.bounds.index_of(coord)]
+	}
+
+	// the only way a player's coord may change, so occupancy stays in step
+	fn set_player_coord(&mut self, moniker: Moniker, coord: Coord2D) {
+		let old = {
+			let player = self.essence.players.get_mut(&moniker).unwrap();
+			let old = player.coord;
+			player.coord = coord;
+			old
+		};
+		let old_index = self.bounds.index_of(old);
+		if self.occupancy[old_index] == Some(moniker) {
+			// someone may have been swapped in already
+			self.occupancy[old_index] = None;
 		}
-		None
+		let new_index = self.bounds.index_of(coord);
+		self.occupancy[new_index] = Some(moniker);
+	}
+
+	// walls may not be pushed or spawned right next to a protected player
+	fn is_protected_near(&self, coord: Coord2D) -> bool {
+		let protected_at = |c: Coord2D| self.get_player_at(c)
+			.map(|p| p.is_protected())
+			.unwrap_or(false);
+		protected_at(coord)
+		|| Direction::ALL.iter().any(|&dir| {
+			!self.coord_would_exit(coord, dir) && protected_at(coord.move_with(dir))
+		})
 	}
 
 	// fn get_mut_player_at(&mut self, coord: Coord2D) -> Option<&mut PlayerObject> {
@@ -131,23 +399,27 @@ impl GameState { // basic stuff
 	// }
 
 	#[inline]
-	fn is_player_at(&self, coord: Coord2D) -> bool {
+	pub fn is_player_at(&self, coord: Coord2D) -> bool {
 		self.get_player_at(coord).is_some()
 	}
 
 	#[inline]
 	pub fn is_wall_at(&self, coord: Coord2D) -> bool {
-		self.essence.wall_override.get(&coord)
-		.map(|x| *x)
-		.unwrap_or_else(|| {
-			self.wall_default[coord.y as usize]
-			.test(coord.x as usize)
-		})
+		self.walls.get(coord.x, coord.y)
 	}
 
 	#[inline]
 	pub fn is_blob_at(&self, coord: Coord2D) -> bool {
-		self.essence.power_blobs.contains(&coord)
+		self.essence.power_blobs.contains_key(&coord)
+	}
+
+	// walls cover up tiles, leaving them inert
+	#[inline]
+	pub fn tile_at(&self, coord: Coord2D) -> Option<Tile> {
+		if self.is_wall_at(coord) {
+			return None;
+		}
+		self.tiles.get(&coord).cloned()
 	}
 
 	pub fn is_something_at(&self, coord: Coord2D) -> bool {
@@ -158,168 +430,424 @@ impl GameState { // basic stuff
 
 	fn set_wall_value(&mut self, coord: Coord2D, value: bool) {
 		if value != self.is_wall_at(coord) {
-			self.essence.wall_override.insert(coord, value);
+			self.walls.set(coord.x, coord.y, value);
+			self.essence.wall_flips.toggle(self.bounds.index_of(coord) as u32);
+			if value {
+				self.non_wall_spaces -= 1;
+			} else {
+				self.non_wall_spaces += 1;
+			}
 		}
 	}
 
-	pub fn try_add_player(&mut self, moniker: Moniker, coord: Coord2D) -> ValidMove {
-		if self.essence.players.contains_key(&moniker)
-		|| self.is_something_at(coord) {
+	pub fn try_add_player(&mut self, moniker: Moniker, coord: Coord2D, team: Team) -> ValidMove {
+		if !self.bounds.contains(coord) // coords come off the wire
+		|| self.is_something_at(coord)
+		|| !self.team_may_enter(team, coord) {
 			return false
 		}
 		let obj = PlayerObject {
-			coord: coord,
+			coord,
 			charge: 3,
+			team,
+			speed_moves: 0,
+			ghost_moves: 0,
+			protected_moves: PlayerObject::SPAWN_PROTECTION_MOVES,
 		};
-		self.essence.players.insert(moniker, obj);
+		self.try_insert_player(moniker, obj)
+	}
+
+	// puts a player in as they are, eg: one that just came into view
+	pub fn try_insert_player(&mut self, moniker: Moniker, player: PlayerObject) -> ValidMove {
+		if self.essence.players.contains_key(&moniker)
+		|| !self.bounds.contains(player.coord)
+		|| self.is_player_at(player.coord) {
+			return false
+		}
+		let index = self.bounds.index_of(player.coord);
+		self.essence.players.insert(moniker, player);
+		self.occupancy[index] = Some(moniker);
 		true
 	}
 
+	// spawn protection runs out with time as well as moves. the server keeps the time
+	pub fn end_protection(&mut self, moniker: Moniker) -> ValidMove {
+		match self.essence.players.get_mut(&moniker) {
+			Some(ref mut player) if player.is_protected() => {
+				player.protected_moves = 0;
+				true
+			},
+			_ => false,
+		}
+	}
+
 	pub fn try_remove_player(&mut self, moniker: Moniker) -> ValidMove {
-		self.essence.players.remove(&moniker).is_some()
+		match self.essence.players.remove(&moniker) {
+			Some(player) => {
+				let index = self.bounds.index_of(player.coord);
+				self.occupancy[index] = None;
+				true
+			},
+			None => false,
+		}
 	}
 
-	pub fn coord_on_boundary(coord: Coord2D) -> bool {
-		coord.x == 0
-		|| coord.y == 0
-		|| coord.x == Self::WIDTH-1
-		|| coord.y == Self::HEIGHT-1
+	#[inline]
+	pub fn coord_on_boundary(&self, coord: Coord2D) -> bool {
+		self.bounds.on_boundary(coord)
 	}
 
-	pub fn coord_would_exit(coord: Coord2D, dir: Direction) -> bool {
-		match dir {
-			Direction::Up => coord.y == 0,
-			Direction::Down => coord.y == Self::HEIGHT-1,
-			Direction::Left => coord.x == 0,
-			Direction::Right => coord.x == Self::WIDTH-1,
-		}
+	#[inline]
+	pub fn base_at(&self, coord: Coord2D) -> Option<Team> {
+		self.bounds.base_at(coord)
+	}
+
+	#[inline]
+	pub fn team_may_enter(&self, team: Team, coord: Coord2D) -> bool {
+		self.bounds.team_may_enter(team, coord)
+	}
+
+	#[inline]
+	pub fn coord_would_exit(&self, coord: Coord2D, dir: Direction) -> bool {
+		self.bounds.would_exit(coord, dir)
 	}
 
-	pub fn player_iter(&self) -> PlayerIter {
+	pub fn player_iter(&self) -> PlayerIter<'_> {
 		PlayerIter(self.essence.players.iter())
 	}
 
-	pub fn blob_iter(&self) -> BlobIter {
+	pub fn blob_iter(&self) -> BlobIter<'_> {
 		BlobIter(self.essence.power_blobs.iter())
 	}
+
+	pub fn tile_iter(&self) -> TileIter<'_> {
+		TileIter(self.tiles.iter())
+	}
 	// WAITING FOR IMPL TRAIT
 	pub fn coord_iter(&self) -> CoordIter {
-		CoordIter { next: Coord2D::NULL }
+		self.bounds.coord_iter()
 	}
 
 	pub fn empty_cell_ratio(&self) -> f32 {
 		(self.num_empty_coords() as f32)
-		/ (Self::TOTAL_COORDS as f32)
+		/ (self.total_coords() as f32)
 	}
 }
 
-impl GameState { /// major stuff
+impl GameState { // major stuff
+	pub fn new_random(fog_radius: Option<u16>, map_gen: MapGen) -> Self {
+		let map = MapSource::Generated {
+			seed: new_random_seed(),
+			gen: map_gen,
+			bounds: Bounds::DEFAULT,
+		};
+		Self::new(map, fog_radius)
+	}
 
-	pub fn new_random() -> Self {
+	// `map` must validate
+	pub fn new(map: MapSource, fog_radius: Option<u16>) -> Self {
 		let essence = GameStateEssence {
 			players: HashMap::new(), 
-			wall_default_seed: new_random_seed(),
-			wall_override: HashMap::new(),
-			power_blobs: HashSet::new(),
+			map,
+			wall_flips: WallFlips::new(),
+			power_blobs: HashMap::new(),
+			team_scores: [0; 2],
+			fog_radius,
 			sync_rng: LCGenerator::new_random_seeded(),
 		};
-		let mut x = Self::from_essence(essence);
+		let mut x = Self::from_essence(essence).expect("invalid map");
 		for _ in 0..Self::NUM_POWER_BLOBS {
-			let coord = x.random_free_spot()
+			let coord = x.sync_random_blob_spot()
 				.expect("nowhere to put blob");
-			x.essence.power_blobs.insert(coord);
+			let kind = PickupKind::random(&mut x.essence.sync_rng);
+			x.essence.power_blobs.insert(coord, kind);
 		}
 		x
 	}
 
-	pub fn from_essence(essence: GameStateEssence) -> Self {
+	pub fn from_essence(mut essence: GameStateEssence) -> Result<Self, MapError> {
+		essence.map.validate()?;
 		// build default wall object. 
-		let mut rng: XorShiftRng = SeedableRng::from_seed(essence.wall_default_seed);
-		let mut wall_default = vec![];
-		let mut wall_count = 0;
-		for y in 0..Self::HEIGHT {
-			let mut row = BitSet::with_capacity(Self::WIDTH as usize);
-			for x in 0..Self::WIDTH {
-				if Self::coord_on_boundary(Coord2D::new(x, y))
-				|| rng.gen_weighted_bool(3) {
-					row.set(x as usize, true);
-					wall_count += 1;
+		let (bounds, mut walls, tiles, spawn_points, blob_spawns) = match essence.map {
+			MapSource::Generated { seed, ref gen, bounds } => {
+				let mut rng: XorShiftRng = SeedableRng::from_seed(seed);
+				let walls = mapgen::generate_map(gen, &mut rng, bounds);
+				let tiles = Self::generate_tiles(&mut rng, &walls, bounds);
+				(bounds, walls, tiles, vec![], vec![])
+			},
+			MapSource::Authored(ref map) => (
+				map.bounds(),
+				map.wall_grid(),
+				map.tile_map(),
+				map.spawn_points.clone(),
+				map.blob_spawns.clone(),
+			),
+		};
+		for &index in essence.wall_flips.indices() {
+			if (index as usize) < bounds.total_coords() {
+				let coord = bounds.coord_of(index as usize);
+				walls.toggle(coord.x, coord.y);
+			}
+		}
+		let wall_count = walls.count_ones();
+		// essences come off the wire. players off the map, or on a cell someone
+		// already has, are left out. in moniker order, so every side agrees who
+		let mut occupancy = vec![None; bounds.total_coords()];
+		let mut monikers: Vec<Moniker> = essence.players.keys().cloned().collect();
+		monikers.sort_by_key(|m| m.0);
+		for moniker in monikers {
+			let coord = essence.players[&moniker].coord;
+			if !bounds.contains(coord) || occupancy[bounds.index_of(coord)].is_some() {
+				warn!("dropped player from essence moniker={} at={:?}", moniker.0, coord);
+				essence.players.remove(&moniker);
+			} else {
+				occupancy[bounds.index_of(coord)] = Some(moniker);
+			}
+		}
+		// the same goes for blobs off the map, in a wall or under a player
+		essence.power_blobs.retain(|&coord, _| {
+			let fits = bounds.contains(coord)
+				&& !walls.get(coord.x, coord.y)
+				&& occupancy[bounds.index_of(coord)].is_none();
+			if !fits {
+				warn!("dropped blob from essence at={:?}", coord);
+			}
+			fits
+		});
+		Ok(GameState {
+			occupancy,
+			essence,
+			bounds,
+			walls,
+			tiles,
+			spawn_points,
+			blob_spawns,
+			non_wall_spaces: bounds.total_coords() - wall_count,
+			next_spawn_point: 0,
+		})
+	}
+
+	// continues with the wall rng, so every client derives the same tiles
+	fn generate_tiles(rng: &mut XorShiftRng, walls: &WallGrid,
+		              bounds: Bounds) -> HashMap<Coord2D, Tile> {
+		let mut tiles = HashMap::new();
+		let placeable = |coord: Coord2D, tiles: &HashMap<Coord2D, Tile>| {
+			!walls.get(coord.x, coord.y)
+			&& bounds.base_at(coord).is_none()
+			&& !tiles.contains_key(&coord)
+		};
+		let pick = |rng: &mut XorShiftRng, tiles: &HashMap<Coord2D, Tile>| {
+			for _ in 0..bounds.total_coords() {
+				let coord = Coord2D::new(
+					rng.gen_range(0, bounds.width),
+					rng.gen_range(0, bounds.height),
+				);
+				if placeable(coord, tiles) {
+					return Some(coord);
+				}
+			}
+			None
+		};
+		for _ in 0..Self::NUM_PORTAL_PAIRS {
+			if let Some(a) = pick(rng, &tiles) {
+				tiles.insert(a, Tile::Pit); // placeholder so `b` can't land here
+				if let Some(b) = pick(rng, &tiles) {
+					tiles.insert(a, Tile::Portal(b));
+					tiles.insert(b, Tile::Portal(a));
+				} else {
+					tiles.remove(&a);
 				}
 			}
-			wall_default.push(row);
 		}
-		GameState {
-			essence: essence,
-			wall_default: wall_default,
-			non_wall_spaces: Self::TOTAL_COORDS as usize - wall_count,
+		for _ in 0..Self::NUM_CONVEYORS {
+			if let Some(coord) = pick(rng, &tiles) {
+				let dir = *rng.choose(&Direction::ALL).unwrap();
+				tiles.insert(coord, Tile::Conveyor(dir));
+			}
+		}
+		for _ in 0..Self::NUM_ICE_PATCHES {
+			if let Some(coord) = pick(rng, &tiles) {
+				tiles.insert(coord, Tile::Ice);
+				for &dir in Direction::ALL.iter() {
+					if bounds.would_exit(coord, dir) { continue; }
+					let neighbour = coord.move_with(dir);
+					if placeable(neighbour, &tiles) {
+						tiles.insert(neighbour, Tile::Ice);
+					}
+				}
+			}
 		}
+		for _ in 0..Self::NUM_PITS {
+			if let Some(coord) = pick(rng, &tiles) {
+				tiles.insert(coord, Tile::Pit);
+			}
+		}
+		tiles
 	}
 
 	pub fn random_free_spot(&self) -> Option<Coord2D> {
-		match self.empty_cell_ratio() {
-			x if x > 0.96 => None, // I give up
-			x if x > 0.6 => { //linear select
-				let mut rng = thread_rng();
-				let choice_index = rng.gen_range(0, self.num_empty_coords());
-				self.coord_iter()
-				.filter(|&coord| !self.is_something_at(coord))
-				.nth(choice_index)
+		let ratio = self.empty_cell_ratio();
+		if ratio < Self::GIVE_UP_BELOW {
+			return None; // I give up
+		}
+		let mut rng = thread_rng();
+		if ratio >= Self::LIST_SELECT_BELOW { // trial and error
+			for _ in 0..Self::SPOT_ATTEMPTS {
+				let coord = Coord2D::new(
+					rng.gen_range(0, self.bounds.width),
+					rng.gen_range(0, self.bounds.height),
+				);
+				if !self.is_something_at(coord) {
+					return Some(coord);
+				}
+			}
+		}
+		//linear select
+		let choice_index = rng.gen_range(0, self.num_empty_coords());
+		self.coord_iter()
+		.filter(|&coord| !self.is_something_at(coord))
+		.nth(choice_index)
+	}
+
+	// like random_free_spot, but never inside the base of another team
+	pub fn random_free_spot_for(&self, team: Team) -> Option<Coord2D> {
+		let spots: Vec<Coord2D> = self.coord_iter()
+			.filter(|&coord| !self.is_something_at(coord)
+				&& self.team_may_enter(team, coord))
+			.collect();
+		thread_rng().choose(&spots).cloned()
+	}
+
+	// free spawn points from the map, if it has any. otherwise anywhere the team
+	// can walk to from its base, so nobody starts sealed in a pocket
+	fn spawn_candidates_for(&self, team: Team) -> Vec<Coord2D> {
+		let spots: Vec<Coord2D> = self.spawn_points.iter()
+			.cloned()
+			.filter(|&coord| !self.is_something_at(coord)
+				&& self.team_may_enter(team, coord))
+			.collect();
+		if !spots.is_empty() {
+			return spots;
+		}
+		let reachable = self.flood_fill(self.bounds.base_corner(team), team);
+		self.coord_iter()
+			.filter(|&coord| reachable[self.bounds.index_of(coord)]
+				&& !self.is_something_at(coord))
+			.collect()
+	}
+
+	// where a newcomer of `team` should appear. not synced: the server sends the result
+	pub fn spawn_spot_for(&mut self, team: Team, policy: SpawnPolicy) -> Option<Coord2D> {
+		match policy {
+			SpawnPolicy::Random => {
+				let spots = self.spawn_candidates_for(team);
+				thread_rng().choose(&spots).cloned()
+			},
+			SpawnPolicy::Farthest => {
+				let spots = self.spawn_candidates_for(team);
+				self.farthest_from_players(&spots)
 			},
-			_ => { // trial and error
-				let mut rng = thread_rng();
-				let mut coord;
-				loop {
-					coord = Coord2D::new(
-						rng.gen_range(0, Self::WIDTH),
-						rng.gen_range(0, Self::HEIGHT),
-					);
-					if !self.is_something_at(coord) {
+			SpawnPolicy::RoundRobin => {
+				let n = self.spawn_points.len();
+				for i in 0..n {
+					let coord = self.spawn_points[(self.next_spawn_point + i) % n];
+					if !self.is_something_at(coord) && self.team_may_enter(team, coord) {
+						self.next_spawn_point = (self.next_spawn_point + i + 1) % n;
 						return Some(coord);
 					}
 				}
-			}
+				self.spawn_spot_for(team, SpawnPolicy::Random)
+			},
+			SpawnPolicy::TeamBase => {
+				let spots: Vec<Coord2D> = self.coord_iter()
+					.filter(|&coord| self.base_at(coord) == Some(team)
+						&& !self.is_something_at(coord))
+					.collect();
+				if spots.is_empty() {
+					return self.spawn_spot_for(team, SpawnPolicy::Farthest);
+				}
+				self.farthest_from_players(&spots)
+			},
 		}
 	}
 
+	// the spot whose nearest player is as far as possible. ties are broken randomly
+	fn farthest_from_players(&self, spots: &[Coord2D]) -> Option<Coord2D> {
+		let nearest_sq = |coord: Coord2D| {
+			self.essence.players.values()
+			.map(|p| {
+				let (dx, dy) = (p.coord.x as i32 - coord.x as i32, p.coord.y as i32 - coord.y as i32);
+				dx*dx + dy*dy
+			})
+			.min()
+			.unwrap_or(0)
+		};
+		let best = spots.iter().map(|&c| nearest_sq(c)).max()?;
+		let tied: Vec<Coord2D> = spots.iter()
+			.cloned()
+			.filter(|&c| nearest_sq(c) == best)
+			.collect();
+		thread_rng().choose(&tied).cloned()
+	}
+
 	pub fn sync_random_free_spot(&mut self) -> Option<Coord2D> {
-		match self.empty_cell_ratio() {
-			x if x > 0.96 => None, // I give up
-			x if x > 0.6 => { //linear select
-				let ne = self.num_empty_coords();
-				let choice_index = {
-					let mut rng = &mut self.essence.sync_rng;
-					rng.gen_range(0, ne)
+		let ratio = self.empty_cell_ratio();
+		if ratio < Self::GIVE_UP_BELOW {
+			return None; // I give up
+		}
+		if ratio >= Self::LIST_SELECT_BELOW { // trial and error
+			// the LCG's low bits repeat quickly, so on small maps its (x, y) pairs
+			// can cycle through a handful of cells that are all taken
+			for _ in 0..Self::SPOT_ATTEMPTS {
+				let coord = {
+					let rng = &mut self.essence.sync_rng;
+					Coord2D::new(
+						rng.gen_range(0, self.bounds.width),
+						rng.gen_range(0, self.bounds.height),
+					)
 				};
-				self.coord_iter()
-				.filter(|&coord| !self.is_something_at(coord))
-				.nth(choice_index)
-			},
-			_ => { // trial and error
-				loop {
-					let coord = {
-						let mut rng = &mut self.essence.sync_rng;
-						Coord2D::new(
-							rng.gen_range(0, Self::WIDTH),
-							rng.gen_range(0, Self::HEIGHT),
-						)
-					};
-					if !self.is_something_at(coord) {
-						return Some(coord);
-					}
+				if !self.is_something_at(coord) {
+					return Some(coord);
 				}
 			}
 		}
+		//linear select
+		let ne = self.num_empty_coords();
+		let choice_index = self.essence.sync_rng.gen_range(0, ne);
+		self.coord_iter()
+		.filter(|&coord| !self.is_something_at(coord))
+		.nth(choice_index)
 	}
 
-	fn try_move_wall(&mut self, src: Coord2D, dir: Direction) -> ValidMove {
-		if Self::coord_would_exit(src, dir)
+	// a free blob spawn point from the map, if it has any. anywhere otherwise
+	fn sync_random_blob_spot(&mut self) -> Option<Coord2D> {
+		let spots: Vec<Coord2D> = self.blob_spawns.iter()
+			.cloned()
+			.filter(|&coord| !self.is_something_at(coord))
+			.collect();
+		if spots.is_empty() {
+			return self.sync_random_free_spot();
+		}
+		let index = self.essence.sync_rng.gen_range(0, spots.len());
+		Some(spots[index])
+	}
+
+	pub fn wall_pushable(&self, src: Coord2D, dir: Direction) -> bool {
+		if self.coord_would_exit(src, dir)
 		|| !self.is_wall_at(src) {
 			return false; // wall doesn't exist or is on boundary
 		}
 		let dest = src.move_with(dir);
-		if self.is_something_at(dest) {
-			return false; // something preventing move
+		!(self.is_something_at(dest)
+		|| self.base_at(dest).is_some()
+		|| self.is_protected_near(dest)) // bases and spawners stay clear
+	}
+
+	fn try_move_wall(&mut self, src: Coord2D, dir: Direction) -> ValidMove {
+		if !self.wall_pushable(src, dir) {
+			return false;
 		}
+		let dest = src.move_with(dir);
 		self.set_wall_value(src, false);
 		self.set_wall_value(dest, true);
 		true
@@ -329,38 +857,324 @@ impl GameState { /// major stuff
 		if !self.contains_player(moniker) { return false; } // no such player
 		let src = self.essence.players.get_mut(&moniker).unwrap().coord;
 
-		if Self::coord_would_exit(src, dir) { return false; } // on boundary
+		if self.coord_would_exit(src, dir) { return false; } // on boundary
 		let dest = src.move_with(dir);
+		let (team, charge, ghosting, protected) = {
+			let player = self.essence.players.get(&moniker).unwrap();
+			(player.team, player.charge, player.ghost_moves > 0, player.is_protected())
+		};
 
-		if self.is_player_at(dest) { return false; } //obstructed
+		if !self.team_may_enter(team, dest) { return false; } // enemy base
+		if self.tile_at(dest) == Some(Tile::Conveyor(dir.opposite())) {
+			return false; // against the flow
+		}
+		if let Some(other) = self.moniker_at(dest) {
+			// a freshly spawned player can't be blocked. they swap with whoever is in the way
+			let (other_team, other_protected) = {
+				let other_player = self.essence.players.get(&other).unwrap();
+				(other_player.team, other_player.is_protected())
+			};
+			if !protected
+			|| other_protected
+			|| self.is_wall_at(dest) // a ghost inside a wall
+			|| !self.team_may_enter(other_team, src) {
+				return false; //obstructed
+			}
+			self.set_player_coord(other, src);
+		}
 		if self.is_wall_at(dest) {
-			if self.essence.players.get(&moniker).unwrap().charge > 0
-			&& self.try_move_wall(dest, dir) {
+			if ghosting && !self.coord_on_boundary(dest) {
+				// pass right through
+			} else if charge > 0 && self.try_move_wall(dest, dir) {
 				//successfully moved wall
-				let player = self.essence.players.get_mut(&moniker).unwrap();
-				player.coord = dest;
-				player.charge -= 1;
-				true
+				self.essence.players.get_mut(&moniker).unwrap().charge -= 1;
 			} else {
 				//failed to move wall 
-				false
+				return false;
 			}
-		} else {
-			// spot was free
-			self.essence.players.get_mut(&moniker)
-			.unwrap().coord = dest;
-			if self.is_blob_at(dest) {
-				self.essence.power_blobs.remove(&dest);
-				let new_blob_at = self.sync_random_free_spot()
-					.expect("nowhere to put blob");
-				self.essence.power_blobs.insert(new_blob_at);
+		}
+		{
+			let player = self.essence.players.get_mut(&moniker).unwrap();
+			player.speed_moves = player.speed_moves.saturating_sub(1);
+			player.ghost_moves = player.ghost_moves.saturating_sub(1);
+			player.protected_moves = player.protected_moves.saturating_sub(1);
+		}
+		if self.enter_cell(moniker, dest) {
+			self.resolve_tiles(moniker, dir);
+		}
+		true
+	}
+
+	// puts the player at `coord` and collects whatever pickup is there.
+	// returns false if a pickup relocated the player elsewhere
+	fn enter_cell(&mut self, moniker: Moniker, coord: Coord2D) -> bool {
+		self.set_player_coord(moniker, coord);
+		let team = self.essence.players.get(&moniker).unwrap().team;
+		if let Some(kind) = self.essence.power_blobs.remove(&coord) {
+			let new_blob_at = self.sync_random_blob_spot()
+				.expect("nowhere to put blob");
+			let new_kind = PickupKind::random(&mut self.essence.sync_rng);
+			self.essence.power_blobs.insert(new_blob_at, new_kind);
+
+			self.essence.team_scores[team.index()] += 1;
+			self.apply_pickup(moniker, kind);
+		}
+		self.essence.players.get(&moniker).unwrap().coord == coord
+	}
+
+	// applies the tile under the player (and whatever that leads onto) after a move
+	fn resolve_tiles(&mut self, moniker: Moniker, mut dir: Direction) {
+		let max_steps = self.bounds.width + self.bounds.height;
+		for _ in 0..max_steps {
+			let (here, team) = {
+				let player = self.essence.players.get(&moniker).unwrap();
+				(player.coord, player.team)
+			};
+			let push = match self.tile_at(here) {
+				None => return,
+				Some(Tile::Pit) => {
+					// climbing out of a pit counts as a respawn
+					self.sync_relocate(moniker);
+					self.essence.players.get_mut(&moniker).unwrap()
+					.protected_moves = PlayerObject::SPAWN_PROTECTION_MOVES;
+					return;
+				},
+				Some(Tile::Portal(partner)) => {
+					if !self.is_player_at(partner)
+					&& !self.is_wall_at(partner)
+					&& self.team_may_enter(team, partner) {
+						self.enter_cell(moniker, partner);
+					}
+					return; // arriving on a portal doesn't send you back
+				},
+				Some(Tile::Conveyor(conveyor_dir)) => conveyor_dir,
+				Some(Tile::Ice) => dir,
+			};
+			if self.coord_would_exit(here, push) { return; }
+			let next = here.move_with(push);
+			if self.is_player_at(next)
+			|| self.is_wall_at(next)
+			|| !self.team_may_enter(team, next)
+			|| self.tile_at(next) == Some(Tile::Conveyor(push.opposite())) {
+				return; // stopped
+			}
+			if !self.enter_cell(moniker, next) {
+				return;
+			}
+			dir = push;
+		}
+	}
+
+	// moves the player to a sync-random spot they're allowed to be in
+	fn sync_relocate(&mut self, moniker: Moniker) {
+		let team = self.essence.players.get(&moniker).unwrap().team;
+		for _ in 0..PlayerObject::TELEPORT_ATTEMPTS {
+			match self.sync_random_free_spot() {
+				Some(coord) if self.team_may_enter(team, coord) => {
+					self.set_player_coord(moniker, coord);
+					return;
+				},
+				_ => (),
+			}
+		}
+	}
 
+	// everything random in here MUST come from sync_rng, as clients replay it
+	fn apply_pickup(&mut self, moniker: Moniker, kind: PickupKind) {
+		match kind {
+			PickupKind::Charge => {
 				let player = self.essence.players.get_mut(&moniker).unwrap();
 				if player.charge < PlayerObject::POWER_LIMIT {
 					player.charge += 1;
 				}
+			},
+			PickupKind::Speed => {
+				self.essence.players.get_mut(&moniker).unwrap()
+				.speed_moves = PlayerObject::SPEED_MOVES;
+			},
+			PickupKind::Ghost => {
+				self.essence.players.get_mut(&moniker).unwrap()
+				.ghost_moves = PlayerObject::GHOST_MOVES;
+			},
+			PickupKind::Teleport => self.sync_relocate(moniker),
+			PickupKind::WallSpawner => {
+				for _ in 0..PlayerObject::WALL_SPAWNER_WALLS {
+					if let Some(coord) = self.sync_random_free_spot() {
+						if self.base_at(coord).is_none() && !self.is_protected_near(coord) {
+							self.set_wall_value(coord, true);
+						}
+					}
+				}
+			},
+		}
+	}
+}
+
+impl GameState { // visibility
+
+	#[inline]
+	pub fn fog_radius(&self) -> Option<u16> {
+		self.essence.fog_radius
+	}
+
+	// within fog radius, with no walls on the line between. without fog, everything is visible
+	pub fn can_see(&self, from: Coord2D, to: Coord2D) -> bool {
+		let radius = match self.essence.fog_radius {
+			Some(r) => r as i64, // squared, any u16 fits
+			None => return true,
+		};
+		let (x0, y0) = (from.x as i32, from.y as i32);
+		let (x1, y1) = (to.x as i32, to.y as i32);
+		let (dx, dy) = (x1 - x0, y1 - y0);
+		if (dx*dx + dy*dy) as i64 > radius*radius {
+			return false;
+		}
+		// bresenham. walls block the view past them, but are themselves visible
+		let (sx, sy) = (dx.signum(), dy.signum());
+		let (adx, ady) = (dx.abs(), -dy.abs());
+		let mut err = adx + ady;
+		let (mut x, mut y) = (x0, y0);
+		loop {
+			if x == x1 && y == y1 {
+				return true;
+			}
+			if (x, y) != (x0, y0)
+			&& self.is_wall_at(Coord2D::new(x as u16, y as u16)) {
+				return false;
+			}
+			let e2 = 2 * err;
+			if e2 >= ady { err += ady; x += sx; }
+			if e2 <= adx { err += adx; y += sy; }
+		}
+	}
+
+	pub fn player_can_see(&self, moniker: Moniker, coord: Coord2D) -> bool {
+		if self.essence.fog_radius.is_none() {
+			return true;
+		}
+		match self.essence.players.get(&moniker) {
+			Some(player) => self.can_see(player.coord, coord),
+			None => false,
+		}
+	}
+
+	// the players the given player can see, themselves included
+	pub fn players_visible_to(&self, moniker: Moniker) -> HashMap<Moniker, PlayerObject> {
+		self.essence.players.iter()
+			.filter(|&(_, p)| self.player_can_see(moniker, p.coord))
+			.map(|(&m, p)| (m, p.clone()))
+			.collect()
+	}
+
+	fn wall_flips_visible_to(&self, moniker: Moniker) -> WallFlips {
+		self.essence.wall_flips
+			.filtered(|index| self.player_can_see(moniker, self.bounds.coord_of(index as usize)))
+	}
+
+	fn blobs_visible_to(&self, moniker: Moniker) -> HashMap<Coord2D, PickupKind> {
+		self.essence.power_blobs.iter()
+			.filter(|&(&c, _)| self.player_can_see(moniker, c))
+			.map(|(&c, &k)| (c, k))
+			.collect()
+	}
+
+	// the essence with everything the given player cannot see stripped out.
+	// clients under fog start from this, and get it again if they fall out of step
+	pub fn essence_visible_to(&self, moniker: Moniker) -> GameStateEssence {
+		if self.essence.fog_radius.is_none() {
+			return self.essence.clone();
+		}
+		GameStateEssence {
+			players: self.players_visible_to(moniker),
+			map: self.essence.map.clone(),
+			wall_flips: self.wall_flips_visible_to(moniker),
+			power_blobs: self.blobs_visible_to(moniker),
+			team_scores: self.essence.team_scores,
+			fog_radius: self.essence.fog_radius,
+			sync_rng: LCGenerator { seed: 0 }, // would leak future blob spots
+		}
+	}
+
+	/*
+	Whether `other`, a state of the same game, shows the given player exactly
+	what this one does: the same players, walls and blobs within their sight,
+	and the same scores. What either holds out of sight doesn't matter, so a
+	fogged client's copy of the game is in step as long as this holds.
+	*/
+	pub fn same_view(&self, other: &GameState, moniker: Moniker) -> bool {
+		self.essence.team_scores == other.essence.team_scores
+		&& self.players_visible_to(moniker) == other.players_visible_to(moniker)
+		&& self.wall_flips_visible_to(moniker) == other.wall_flips_visible_to(moniker)
+		&& self.blobs_visible_to(moniker) == other.blobs_visible_to(moniker)
+	}
+}
+
+impl GameState { // sanity
+
+	/*
+	What must hold between any two moves. Everything derived from the essence
+	must agree with it, no two players or blobs may share a cell, and no blob may
+	sit in a wall. Players may, while ghosting. Under fog a client only sees some
+	of the blobs, so their count is only checked in full.
+	*/
+	pub fn check_invariants(&self) -> Result<(), String> {
+		let total = self.bounds.total_coords();
+		let flips = self.essence.wall_flips.indices();
+		if flips.windows(2).any(|w| w[0] >= w[1]) {
+			return Err(format!("wall flips not sorted and unique: {:?}", flips));
+		}
+		if let Some(&index) = flips.iter().find(|&&index| index as usize >= total) {
+			return Err(format!("wall flip {} out of bounds", index));
+		}
+		let rebuilt = GameState::from_essence(self.essence.clone()).map_err(|e| e.to_string())?;
+		if rebuilt.walls != self.walls {
+			return Err("walls differ from the layout with its flips applied".to_owned());
+		}
+		let wall_count = self.walls.count_ones();
+		if self.non_wall_spaces != total - wall_count {
+			return Err(format!("non_wall_spaces is {} but there are {} walls in {} cells",
+				self.non_wall_spaces, wall_count, total));
+		}
+		let mut taken: HashMap<Coord2D, String> = HashMap::new();
+		for (&moniker, player) in self.essence.players.iter() {
+			if !self.bounds.contains(player.coord) {
+				return Err(format!("{:?} out of bounds at {:?}", moniker, player.coord));
+			}
+			if let Some(other) = taken.insert(player.coord, format!("{:?}", moniker)) {
+				return Err(format!("{:?} and {} share {:?}", moniker, other, player.coord));
+			}
+			if self.moniker_at(player.coord) != Some(moniker) {
+				return Err(format!("occupancy lost {:?} at {:?}", moniker, player.coord));
+			}
+		}
+		let occupied = self.occupancy.iter().filter(|x| x.is_some()).count();
+		if occupied != self.essence.players.len() {
+			return Err(format!("occupancy has {} players, essence has {}",
+				occupied, self.essence.players.len()));
+		}
+		for (&coord, &kind) in self.essence.power_blobs.iter() {
+			if !self.bounds.contains(coord) {
+				return Err(format!("{:?} blob out of bounds at {:?}", kind, coord));
+			}
+			if self.is_wall_at(coord) {
+				return Err(format!("{:?} blob in a wall at {:?}", kind, coord));
 			}
-			true
+			if let Some(other) = taken.insert(coord, format!("{:?} blob", kind)) {
+				return Err(format!("{:?} blob and {} share {:?}", kind, other, coord));
+			}
+		}
+		let blobs = self.essence.power_blobs.len();
+		let expected = Self::NUM_POWER_BLOBS as usize;
+		if blobs > expected || (self.essence.fog_radius.is_none() && blobs != expected) {
+			return Err(format!("{} blobs, expected {}", blobs, expected));
+		}
+		Ok(())
+	}
+
+	// panics with whatever check_invariants found. for tests and debugging
+	pub fn assert_invariants(&self) {
+		if let Err(msg) = self.check_invariants() {
+			panic!("GameState invariant broken: {}", msg);
 		}
 	}
 }
@@ -372,36 +1186,133 @@ impl<'a> Iterator for PlayerIter<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
 }
 
-pub struct BlobIter<'a>(::std::collections::hash_set::Iter<'a, Coord2D>);
+pub struct BlobIter<'a>(::std::collections::hash_map::Iter<'a, Coord2D, PickupKind>);
 impl<'a> Iterator for BlobIter<'a> {
-    type Item = &'a Coord2D;
+    type Item = (&'a Coord2D, &'a PickupKind);
+    fn next(&mut self) -> Option<Self::Item> { self.0.next() }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+pub struct TileIter<'a>(::std::collections::hash_map::Iter<'a, Coord2D, Tile>);
+impl<'a> Iterator for TileIter<'a> {
+    type Item = (&'a Coord2D, &'a Tile);
     fn next(&mut self) -> Option<Self::Item> { self.0.next() }
     fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
 }
 
 pub struct CoordIter {
 	next: Coord2D,
+	bounds: Bounds,
 }
 impl Iterator for CoordIter {
     type Item = Coord2D;
 fn next(&mut self) -> Option<Self::Item> {
-		if self.next.x >= GameState::WIDTH {
+		if self.next.x >= self.bounds.width {
 			self.next.x = 0;
 			self.next.y += 1;
-			if self.next.y >= GameState::HEIGHT {
-				return None;
-			}
+		}
+		if self.next.y >= self.bounds.height {
+			return None;
 		}
 		let was = self.next;
 		self.next = Coord2D::new(was.x+1, was.y);
 		Some(was)
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
-    	let x = GameState::TOTAL_COORDS;
+    	let x = self.bounds.total_coords();
     	(x, Some(x))
     }
 }
 
+// the size of the board, and the parts of its layout that follow from that
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Bounds {
+	pub width: u16,
+	pub height: u16,
+}
+impl Bounds {
+	pub const DEFAULT: Bounds = Bounds { width: 30, height: 22 };
+	pub const BASE_SIZE: u16 = 4;
+
+	#[inline]
+	pub fn new(width: u16, height: u16) -> Self {
+		Bounds { width, height }
+	}
+
+	#[inline]
+	pub fn total_coords(self) -> usize {
+		self.width as usize * self.height as usize
+	}
+
+	#[inline]
+	pub fn contains(self, coord: Coord2D) -> bool {
+		coord.x < self.width && coord.y < self.height
+	}
+
+	pub fn on_boundary(self, coord: Coord2D) -> bool {
+		coord.x == 0
+		|| coord.y == 0
+		|| coord.x == self.width-1
+		|| coord.y == self.height-1
+	}
+
+	pub fn would_exit(self, coord: Coord2D, dir: Direction) -> bool {
+		match dir {
+			Direction::Up => coord.y == 0,
+			Direction::Down => coord.y == self.height-1,
+			Direction::Left => coord.x == 0,
+			Direction::Right => coord.x == self.width-1,
+		}
+	}
+
+	// bases are BASE_SIZE squares in opposite corners, just inside the boundary
+	pub fn base_at(self, coord: Coord2D) -> Option<Team> {
+		let near = |v: u16| (1..=Self::BASE_SIZE).contains(&v);
+		let far = |v: u16, max: u16| v < max-1 && v + 1 + Self::BASE_SIZE >= max;
+		if near(coord.x) && near(coord.y) {
+			Some(Team::Red)
+		} else if far(coord.x, self.width) && far(coord.y, self.height) {
+			Some(Team::Blue)
+		} else {
+			None
+		}
+	}
+
+	// players may go anywhere except the bases of other teams
+	#[inline]
+	pub fn team_may_enter(self, team: Team, coord: Coord2D) -> bool {
+		match self.base_at(coord) {
+			Some(owner) => owner == team,
+			None => true,
+		}
+	}
+
+	// the innermost corner cell of the team's base, just off the boundary
+	pub fn base_corner(self, team: Team) -> Coord2D {
+		match team {
+			Team::Red => Coord2D::new(1, 1),
+			Team::Blue => Coord2D::new(self.width - 2, self.height - 2),
+		}
+	}
+
+	#[inline]
+	pub fn index_of(self, coord: Coord2D) -> usize {
+		coord.y as usize * self.width as usize + coord.x as usize
+	}
+
+	#[inline]
+	pub fn coord_of(self, index: usize) -> Coord2D {
+		Coord2D::new(
+			(index % self.width as usize) as u16,
+			(index / self.width as usize) as u16,
+		)
+	}
+
+	pub fn coord_iter(self) -> CoordIter {
+		CoordIter { next: Coord2D::NULL, bounds: self }
+	}
+}
+
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Coord2D {
 	pub x: u16,
@@ -412,7 +1323,7 @@ impl Coord2D {
 
 	#[inline]
 	pub fn new(x: u16, y: u16) -> Self {
-		Coord2D { x:x, y:y }
+		Coord2D { x, y }
 	}
 
 	// ASSUMES ITS VALID
@@ -430,3 +1341,17 @@ impl Coord2D {
 pub enum Direction {
 	Left, Right, Up, Down,
 }
+impl Direction {
+	pub const ALL: [Direction; 4] = [
+		Direction::Up, Direction::Down, Direction::Left, Direction::Right
+	];
+
+	pub fn opposite(self) -> Direction {
+		match self {
+			Direction::Up => Direction::Down,
+			Direction::Down => Direction::Up,
+			Direction::Left => Direction::Right,
+			Direction::Right => Direction::Left,
+		}
+	}
+}
@@ -0,0 +1,243 @@
+use serde::{
+	de,
+	Serialize,
+	Serializer,
+	Deserialize,
+	Deserializer,
+};
+use std::fmt;
+
+/////////////////////////////////////////////////////////////
+
+// one bit per cell, row after row, packed 64 to a word
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitGrid {
+	width: u16,
+	height: u16,
+	words: Vec<u64>,
+}
+impl BitGrid {
+	pub fn new(width: u16, height: u16, value: bool) -> Self {
+		let cells = width as usize * height as usize;
+		let fill = if value { !0 } else { 0 };
+		let mut grid = BitGrid {
+			width,
+			height,
+			words: vec![fill; cells.div_ceil(64)],
+		};
+		grid.clear_padding();
+		grid
+	}
+
+	#[inline]
+	pub fn width(&self) -> u16 {
+		self.width
+	}
+
+	#[inline]
+	pub fn height(&self) -> u16 {
+		self.height
+	}
+
+	#[inline]
+	fn index(&self, x: u16, y: u16) -> usize {
+		y as usize * self.width as usize + x as usize
+	}
+
+	#[inline]
+	pub fn get(&self, x: u16, y: u16) -> bool {
+		let i = self.index(x, y);
+		self.words[i / 64] & (1 << (i % 64)) != 0
+	}
+
+	#[inline]
+	pub fn set(&mut self, x: u16, y: u16, value: bool) {
+		let i = self.index(x, y);
+		if value {
+			self.words[i / 64] |= 1 << (i % 64);
+		} else {
+			self.words[i / 64] &= !(1 << (i % 64));
+		}
+	}
+
+	#[inline]
+	pub fn toggle(&mut self, x: u16, y: u16) {
+		let i = self.index(x, y);
+		self.words[i / 64] ^= 1 << (i % 64);
+	}
+
+	pub fn count_ones(&self) -> usize {
+		self.words.iter().map(|w| w.count_ones() as usize).sum()
+	}
+
+	// bits past the last cell stay 0, so count_ones and == behave
+	fn clear_padding(&mut self) {
+		let cells = self.width as usize * self.height as usize;
+		if !cells.is_multiple_of(64) {
+			if let Some(last) = self.words.last_mut() {
+				*last &= (1 << (cells % 64)) - 1;
+			}
+		}
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+/*
+The cells whose wall differs from the map's own layout, as sorted cell
+indices (see Bounds::index_of). In memory it's just the sorted list.
+On the wire it's the gaps between consecutive indices as LEB128 varints,
+so a handful of pushed walls costs a handful of bytes.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WallFlips(Vec<u32>);
+impl WallFlips {
+	pub fn new() -> Self {
+		WallFlips(vec![])
+	}
+
+	#[inline]
+	pub fn contains(&self, index: u32) -> bool {
+		self.0.binary_search(&index).is_ok()
+	}
+
+	// a cell flipped twice is back to its layout value, so it leaves the set
+	pub fn toggle(&mut self, index: u32) {
+		match self.0.binary_search(&index) {
+			Ok(at) => { self.0.remove(at); },
+			Err(at) => self.0.insert(at, index),
+		}
+	}
+
+	#[inline]
+	pub fn indices(&self) -> &[u32] {
+		&self.0
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	// only the flips for which `keep` holds
+	pub fn filtered<F: Fn(u32) -> bool>(&self, keep: F) -> Self {
+		WallFlips(self.0.iter().cloned().filter(|&i| keep(i)).collect())
+	}
+
+	// the flips in exactly one of the two. toggling these onto `other` gives `self`
+	pub fn symmetric_difference(&self, other: &Self) -> Self {
+		let (a, b) = (&self.0, &other.0);
+		let mut out = Vec::with_capacity(a.len() + b.len());
+		let (mut i, mut j) = (0, 0);
+		while i < a.len() && j < b.len() {
+			if a[i] < b[j] {
+				out.push(a[i]);
+				i += 1;
+			} else if b[j] < a[i] {
+				out.push(b[j]);
+				j += 1;
+			} else {
+				i += 1;
+				j += 1;
+			}
+		}
+		out.extend_from_slice(&a[i..]);
+		out.extend_from_slice(&b[j..]);
+		WallFlips(out)
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(self.0.len() * 2);
+		let mut prev = 0;
+		for &index in self.0.iter() {
+			let mut gap = index - prev;
+			prev = index;
+			loop {
+				let byte = (gap & 0x7f) as u8;
+				gap >>= 7;
+				if gap == 0 {
+					bytes.push(byte);
+					break;
+				}
+				bytes.push(byte | 0x80);
+			}
+		}
+		bytes
+	}
+
+	// None if the bytes run out mid-varint, or a gap or the indices overflow
+	pub fn decode(bytes: &[u8]) -> Option<Self> {
+		let mut indices = vec![];
+		let mut prev: u32 = 0;
+		let mut gap: u32 = 0;
+		let mut shift = 0;
+		for &byte in bytes {
+			let payload = (byte & 0x7f) as u32;
+			// the fifth byte has room for just the top 4 bits
+			if shift >= 32 || (shift == 28 && payload > 0x0f) {
+				return None;
+			}
+			gap |= payload << shift;
+			if byte & 0x80 != 0 {
+				shift += 7;
+				continue;
+			}
+			// only the first index may be 0 away from its predecessor
+			if gap == 0 && !indices.is_empty() {
+				return None;
+			}
+			prev = prev.checked_add(gap)?;
+			indices.push(prev);
+			gap = 0;
+			shift = 0;
+		}
+		if shift != 0 {
+			return None;
+		}
+		Some(WallFlips(indices))
+	}
+}
+
+impl Serialize for WallFlips {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_bytes(&self.encode())
+	}
+}
+
+impl<'de> Deserialize<'de> for WallFlips {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserializer.deserialize_bytes(WallFlipsVisitor)
+	}
+}
+
+struct WallFlipsVisitor;
+impl<'de> de::Visitor<'de> for WallFlipsVisitor {
+	type Value = WallFlips;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "varint-encoded wall flips")
+	}
+
+	fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<WallFlips, E> {
+		WallFlips::decode(bytes)
+		.ok_or_else(|| E::custom("malformed wall flips"))
+	}
+
+	fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<WallFlips, E> {
+		self.visit_bytes(&bytes)
+	}
+
+	// for formats that write bytes as a plain sequence
+	fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<WallFlips, A::Error> {
+		let mut bytes = vec![];
+		while let Some(byte) = seq.next_element::<u8>()? {
+			bytes.push(byte);
+		}
+		self.visit_bytes(&bytes)
+	}
+}
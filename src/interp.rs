@@ -0,0 +1,107 @@
+use ::common::{
+	MOVE_PERIOD,
+	secs_f32,
+};
+use ::anim::lerp;
+use ::game::*;
+
+use std::{
+	collections::{
+		HashMap,
+		VecDeque,
+	},
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+pub const DEFAULT_INTERP_DELAY: Duration = Duration::from_millis(100);
+
+/*
+Where other players are drawn. Every cell a player was seen in is kept with
+the time it arrived, and players are drawn `delay` in the past, gliding
+between the two cells either side of that moment. Jumps further than one cell
+(portals, pits, teleports, ice) aren't glided: the player just appears there.
+*/
+#[derive(Debug)]
+pub struct Interpolator {
+	delay: Duration,
+	tracks: HashMap<Moniker, VecDeque<(Instant, Coord2D)>>,
+}
+impl Interpolator {
+	pub fn new(delay: Duration) -> Self {
+		Interpolator {
+			delay,
+			tracks: HashMap::new(),
+		}
+	}
+
+	// notes where everyone in `game_state` is as of `now`, and forgets whoever left
+	pub fn record(&mut self, game_state: &GameState, now: Instant) {
+		self.tracks.retain(|&moniker, _| game_state.contains_player(moniker));
+		for (&moniker, player) in game_state.player_iter() {
+			let track = self.tracks.entry(moniker).or_default();
+			let last = track.back().cloned();
+			match last {
+				Some((_, c)) if c == player.coord => continue,
+				Some((last_at, c)) => {
+					// someone who stood still glides off at move speed, not from when they stopped
+					if let Some(glide_from) = now.checked_sub(MOVE_PERIOD) {
+						if last_at < glide_from {
+							track.push_back((glide_from, c));
+						}
+					}
+				},
+				None => (),
+			}
+			track.push_back((now, player.coord));
+		}
+		if let Some(render_at) = now.checked_sub(self.delay) {
+			for track in self.tracks.values_mut() {
+				// keep the last sample at or before render_at, and everything after
+				while track.len() >= 2 && track[1].0 <= render_at {
+					track.pop_front();
+				}
+			}
+		}
+	}
+
+	// the cell position to draw `moniker` at, in fractions of a cell
+	pub fn position(&self, moniker: Moniker, now: Instant) -> Option<(f32, f32)> {
+		let track = self.tracks.get(&moniker)?;
+		let as_f32 = |c: Coord2D| (c.x as f32, c.y as f32);
+		let render_at = match now.checked_sub(self.delay) {
+			Some(t) => t,
+			None => return track.front().map(|&(_, c)| as_f32(c)),
+		};
+		// the last sample at or before render_at, and the one after it
+		let before = track.iter().take_while(|&&(t, _)| t <= render_at).count();
+		if before == 0 {
+			return track.front().map(|&(_, c)| as_f32(c));
+		}
+		let (from_at, from) = track[before - 1];
+		let (to_at, to) = match track.get(before) {
+			Some(&next) => next,
+			None => return Some(as_f32(from)),
+		};
+		let dist = (from.x as i32 - to.x as i32).abs() + (from.y as i32 - to.y as i32).abs();
+		if dist > 1 {
+			return Some(as_f32(from));
+		}
+		let t = secs_f32(render_at - from_at) / secs_f32(to_at - from_at);
+		Some(lerp(from, to, t))
+	}
+
+	// true while someone is still drawn somewhere between cells
+	pub fn in_motion(&self, now: Instant) -> bool {
+		let render_at = match now.checked_sub(self.delay) {
+			Some(t) => t,
+			None => return true,
+		};
+		self.tracks.values().any(|track| track.len() >= 2
+			&& track.back().map(|&(t, _)| t > render_at).unwrap_or(false))
+	}
+}
@@ -0,0 +1,29 @@
+#[macro_use] extern crate serde_derive;
+extern crate serde;
+extern crate mio;
+extern crate mio_extras;
+extern crate middleman;
+extern crate rand;
+#[macro_use] extern crate log;
+#[cfg(feature = "gui")] extern crate ggez;
+#[cfg(feature = "tui")] extern crate termion;
+
+pub mod game;
+pub mod grid;
+pub mod mapgen;
+pub mod pathing;
+pub mod mapfile;
+pub mod common;
+pub mod logging;
+pub mod snapshot;
+pub mod interp;
+pub mod anim;
+pub mod netstats;
+pub mod metrics;
+pub mod chat;
+pub mod clock;
+pub mod transport;
+pub mod server;
+#[cfg(feature = "gui")] pub mod client;
+#[cfg(feature = "gui")] pub mod editor;
+#[cfg(feature = "tui")] pub mod tui;
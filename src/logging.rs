@@ -0,0 +1,110 @@
+use log::{
+	self,
+	LevelFilter,
+	Log,
+	Metadata,
+	Record,
+};
+
+use std::{
+	cmp::Reverse,
+	fs::File,
+	io::{
+		self,
+		Write,
+	},
+	sync::Mutex,
+	time::Instant,
+};
+
+use ::common::secs_f32;
+
+/////////////////////////////////////////////////////////////
+
+pub const DEFAULT_LOG_SPEC: &str = "warn,pinggame::chat=info"; // the server shows chat unless told otherwise
+
+/*
+A level for everything, optionally followed by levels for single modules, eg:
+`info,pinggame::server=debug`. A module's level also covers its submodules.
+Messages carry their details as `key=value` fields after a short description,
+eg: `recv tok=3 moniker=Q kind=ReqMove`, so they can be grepped for.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSpec {
+	default: LevelFilter,
+	modules: Vec<(String, LevelFilter)>, // most specific first
+}
+impl LogSpec {
+	pub fn parse(spec: &str) -> Result<Self, String> {
+		let mut default = LevelFilter::Warn;
+		let mut modules = vec![];
+		for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+			let mut halves = part.splitn(2, '=');
+			let first = halves.next().unwrap();
+			match halves.next() {
+				Some(level) => modules.push((first.to_owned(), parse_level(level)?)),
+				None => default = parse_level(first)?,
+			}
+		}
+		modules.sort_by_key(|m| Reverse(m.0.len())); // longest, so most specific, first
+		Ok(LogSpec { default, modules })
+	}
+
+	fn filter_for(&self, target: &str) -> LevelFilter {
+		for &(ref module, level) in self.modules.iter() {
+			if target == module || target.starts_with(&format!("{}::", module)) {
+				return level;
+			}
+		}
+		self.default
+	}
+
+	fn max_level(&self) -> LevelFilter {
+		self.modules.iter().map(|&(_, l)| l).fold(self.default, ::std::cmp::max)
+	}
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, String> {
+	s.trim().parse::<LevelFilter>()
+		.map_err(|_| format!("Unknown log level `{}`. Choose off, error, warn, info, debug or trace", s))
+}
+
+struct Logger {
+	spec: LogSpec,
+	started: Instant,
+	out: Mutex<Box<dyn Write + Send>>,
+}
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= self.spec.filter_for(metadata.target())
+	}
+
+	fn log(&self, record: &Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		let _ = writeln!(self.out.lock().unwrap(), "{:9.3} {:<5} {}: {}",
+			secs_f32(self.started.elapsed()), record.level(), record.target(), record.args());
+	}
+
+	fn flush(&self) {
+		let _ = self.out.lock().unwrap().flush();
+	}
+}
+
+// call once, before anything is logged. without a file, logs go to stderr
+pub fn init(spec: &str, file: Option<&str>) -> Result<(), String> {
+	let spec = LogSpec::parse(spec)?;
+	let out: Box<dyn Write + Send> = match file {
+		Some(path) => Box::new(File::create(path)
+			.map_err(|e| format!("Couldn't open log file `{}`: {}", path, e))?),
+		None => Box::new(io::stderr()),
+	};
+	log::set_max_level(spec.max_level());
+	let logger = Logger {
+		spec,
+		started: Instant::now(),
+		out: Mutex::new(out),
+	};
+	log::set_boxed_logger(Box::new(logger)).map_err(|e| format!("Couldn't start logging: {}", e))
+}
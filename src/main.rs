@@ -1,25 +1,31 @@
-
-#[macro_use] extern crate serde_derive;
-extern crate serde;
-extern crate mio;
-extern crate mio_extras;
-extern crate middleman;
+extern crate pinggame;
 extern crate clap;
-extern crate rand;
-extern crate ggez;
-extern crate bitset;
+#[macro_use] extern crate log;
 
 use clap::App;
 use std::{
 	net::SocketAddr,
+	time::Duration,
 };
 
-mod game;
-use game::Moniker;
-
-mod common;
-mod server;
-mod client;
+use pinggame::server;
+#[cfg(feature = "gui")]
+use pinggame::{
+	client,
+	editor,
+};
+#[cfg(feature = "tui")]
+use pinggame::tui;
+use pinggame::game::{
+	Moniker,
+	Team,
+	SpawnPolicy,
+};
+use pinggame::mapgen::MapGen;
+use pinggame::mapfile::MapFile;
+use pinggame::interp::DEFAULT_INTERP_DELAY;
+use pinggame::logging;
+use pinggame::metrics::DEFAULT_CSV_PERIOD;
 
 // fn debug_testing() {
 // 	let addr: SocketAddr = "127.0.0.1:8008".parse().unwrap();
@@ -39,17 +45,47 @@ fn main() {
 	        .author("C. Esterhuyse <christopher.esterhuyse@gmail.com>")
 	        .about("A super small rust server client toy game for testing network RTT.")
 	        .args_from_usage("-m, --moniker=[CHAR] 'Choose a character-moniker for this game session. eg: `$`'
-	                         <ip> 'Sets the bind/connect addr'")
+	                         -t, --team=[TEAM] 'Request to join team `red` or `blue`. Omit to be auto-balanced'
+	                         -f, --fog=[RADIUS] 'Server only. Players only see RADIUS cells around them, not through walls'
+	                         -g, --generator=[NAME] 'Server only. Map generator: noise, caves, maze, rooms or arena'
+	                         --map=[FILE] 'Server only. Play on a hand-made map file instead of a generated one'
+	                         -s, --spawn=[POLICY] 'Server only. Where newcomers appear: random, farthest, roundrobin or base'
+	                         --snapshots=[MS] 'Server only. Send each client what changed since its last snapshot every MS milliseconds'
+	                         --metrics=[ADDR] 'Server only. Serve Prometheus metrics over HTTP at ADDR. eg: `127.0.0.1:9100`'
+	                         --metrics-csv=[FILE] 'Server only. Append a line of metrics to FILE every 10 seconds'
+	                         --metrics-csv-period=[MS] 'Server only. Append the metrics line every MS milliseconds instead'
+	                         --interp=[MS] 'Client only. Draw other players MS milliseconds in the past, gliding between cells'
+	                         --tui 'Client only. Play in the terminal instead of a window. Needs no SDL2'
+	                         --log-level=[SPEC] 'Which log messages to show. eg: `info` or `warn,pinggame::server=debug`. Defaults to `warn,pinggame::chat=info`'
+	                         --log-file=[FILE] 'Write log messages to FILE instead of the terminal. With --tui, nothing is logged without it'
+	                         -e, --edit=[FILE] 'Open FILE in the map editor instead of playing. Needs no server'
+	                         [ip] 'Sets the bind/connect addr'")
 	        .get_matches();
 
-    // You can check the value provided by positional arguments, or option arguments
-    if let Some(ip) = matches.value_of("ip") {
-        println!("Value for server: {}", ip);
-    }
+	let log_spec = if matches.is_present("tui") && !matches.is_present("log-file") {
+		"off" // the terminal client's board would be scribbled over
+	} else {
+		matches.value_of("log-level").unwrap_or(logging::DEFAULT_LOG_SPEC)
+	};
+	if let Err(e) = logging::init(log_spec, matches.value_of("log-file")) {
+		println!("{}", e);
+		return;
+	}
+
+	if let Some(path) = matches.value_of("edit") {
+		enter_editor(path);
+		return;
+	}
 
-    let ip = matches.value_of("ip").unwrap();
+    let ip = match matches.value_of("ip") {
+    	Some(ip) => ip,
+    	None => {
+    		println!("You need to provide an ip. Good example: `127.0.0.1:8000`");
+    		return;
+    	},
+    };
 	if let Ok(addr) = ip.parse::<SocketAddr>() {
-		println!("ADDR {:?}", &addr);
+		debug!("parsed addr={}", addr);
 		match matches.value_of("moniker") {
 	    	Some(moniker) => {
 	    		if moniker.len() != 1 {
@@ -57,13 +93,147 @@ fn main() {
 	    			return;
 	    		}
 	    		let my_moniker = Moniker(moniker.chars().next().unwrap());
+	    		let my_team = match matches.value_of("team") {
+	    			Some(team_str) => match team_str.parse::<Team>() {
+	    				Ok(team) => Some(team),
+	    				Err(_) => {
+	    					println!("Unknown team `{}`. Choose `red` or `blue`.", team_str);
+	    					return;
+	    				},
+	    			},
+	    			None => None,
+	    		};
+	    		let interp_delay = match matches.value_of("interp") {
+	    			Some(ms_str) => match ms_str.parse::<u64>() {
+	    				Ok(ms) => Duration::from_millis(ms),
+	    				Err(_) => {
+	    					println!("Interpolation delay must be a whole number of milliseconds. eg: `100`");
+	    					return;
+	    				},
+	    			},
+	    			None => DEFAULT_INTERP_DELAY,
+	    		};
 	    		println!("Welcome, player `{}`.", my_moniker.0);
-				client::client_enter(&addr, my_moniker);
+	    		if matches.is_present("tui") {
+	    			enter_tui(&addr, my_moniker, my_team);
+	    		} else {
+	    			enter_gui(&addr, my_moniker, my_team, interp_delay);
+	    		}
+	    	},
+	    	None => {
+	    		let fog_radius = match matches.value_of("fog") {
+	    			Some(radius_str) => match radius_str.parse::<u16>() {
+	    				Ok(radius) => Some(radius),
+	    				Err(_) => {
+	    					println!("Fog radius must be a whole number. eg: `6`");
+	    					return;
+	    				},
+	    			},
+	    			None => None,
+	    		};
+	    		let map_gen = match matches.value_of("generator") {
+	    			Some(gen_str) => match gen_str.parse::<MapGen>() {
+	    				Ok(map_gen) => map_gen,
+	    				Err(_) => {
+	    					println!("Unknown generator `{}`. Choose one of {:?}", gen_str, MapGen::NAMES);
+	    					return;
+	    				},
+	    			},
+	    			None => MapGen::default(),
+	    		};
+	    		let map_file = match matches.value_of("map") {
+	    			Some(path) => match MapFile::load(path) {
+	    				Ok(map) => Some(map),
+	    				Err(e) => {
+	    					println!("Couldn't load map `{}`: {}", path, e);
+	    					return;
+	    				},
+	    			},
+	    			None => None,
+	    		};
+	    		let spawn_policy = match matches.value_of("spawn") {
+	    			Some(policy_str) => match policy_str.parse::<SpawnPolicy>() {
+	    				Ok(policy) => policy,
+	    				Err(_) => {
+	    					println!("Unknown spawn policy `{}`. Choose one of {:?}", policy_str, SpawnPolicy::NAMES);
+	    					return;
+	    				},
+	    			},
+	    			None => SpawnPolicy::default(),
+	    		};
+	    		let snapshot_period = match matches.value_of("snapshots") {
+	    			Some(ms_str) => match ms_str.parse::<u64>() {
+	    				Ok(ms) => Some(Duration::from_millis(ms)),
+	    				Err(_) => {
+	    					println!("Snapshot period must be a whole number of milliseconds. eg: `1000`");
+	    					return;
+	    				},
+	    			},
+	    			None => None,
+	    		};
+	    		let metrics_addr = match matches.value_of("metrics") {
+	    			Some(addr_str) => match addr_str.parse::<SocketAddr>() {
+	    				Ok(addr) => Some(addr),
+	    				Err(_) => {
+	    					println!("Couldn't parse metrics addr `{}`. Good example: `127.0.0.1:9100`", addr_str);
+	    					return;
+	    				},
+	    			},
+	    			None => None,
+	    		};
+	    		let csv_period = match matches.value_of("metrics-csv-period") {
+	    			Some(ms_str) => match ms_str.parse::<u64>() {
+	    				Ok(ms) => Duration::from_millis(ms),
+	    				Err(_) => {
+	    					println!("Metrics CSV period must be a whole number of milliseconds. eg: `10000`");
+	    					return;
+	    				},
+	    			},
+	    			None => DEFAULT_CSV_PERIOD,
+	    		};
+	    		let metrics_csv = matches.value_of("metrics-csv").map(|path| (path.to_owned(), csv_period));
+	    		let config = server::ServerConfig {
+	    			fog_radius,
+	    			map_gen,
+	    			map_file,
+	    			spawn_policy,
+	    			snapshot_period,
+	    			metrics_addr,
+	    			metrics_csv,
+	    			bots: server::DEFAULT_BOTS,
+	    		};
+	    		server::server_enter(&addr, config);
 	    	},
-	    	None => server::server_enter(&addr),
 	    };
 	} else {
 		println!("Couldn't parse ip string `{}`. Good example: `127.0.0.1:8000`", ip);
 	}
 }
 
+#[cfg(feature = "gui")]
+fn enter_editor(path: &str) {
+	editor::editor_enter(path);
+}
+#[cfg(not(feature = "gui"))]
+fn enter_editor(_path: &str) {
+	println!("This build has no map editor. Rebuild with `--features gui`.");
+}
+
+#[cfg(feature = "gui")]
+fn enter_gui(addr: &SocketAddr, my_moniker: Moniker, my_team: Option<Team>, interp_delay: Duration) {
+	client::client_enter(addr, my_moniker, my_team, interp_delay);
+}
+#[cfg(not(feature = "gui"))]
+fn enter_gui(addr: &SocketAddr, my_moniker: Moniker, my_team: Option<Team>, _interp_delay: Duration) {
+	// no window to open, so the terminal will have to do
+	enter_tui(addr, my_moniker, my_team);
+}
+
+#[cfg(feature = "tui")]
+fn enter_tui(addr: &SocketAddr, my_moniker: Moniker, my_team: Option<Team>) {
+	tui::tui_enter(addr, my_moniker, my_team);
+}
+#[cfg(not(feature = "tui"))]
+fn enter_tui(_addr: &SocketAddr, _my_moniker: Moniker, _my_team: Option<Team>) {
+	println!("This build has no terminal client. Rebuild with `--features tui`.");
+}
@@ -0,0 +1,317 @@
+use ::game::*;
+use ::mapgen::{
+	self,
+	MapGen,
+	WallGrid,
+};
+use std::{
+	fmt,
+	fs,
+	io,
+	path::Path,
+	collections::HashMap,
+};
+
+/*
+A hand-authored map. On disk it's plain text, one character per cell:
+
+	#  wall                 .  floor
+	S  spawn point          *  blob spawn point
+	1-9  portal. the two cells with the same digit are a pair
+	^ v < >  conveyor       ~  ice       x  pit
+
+Every row must be the same length. Empty lines and lines starting
+with `//` are ignored. The bases are always in the top-left and
+bottom-right corners, and must be left open.
+*/
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapFile {
+	pub width: u16,
+	pub height: u16,
+	pub walls: Vec<Coord2D>,
+	pub spawn_points: Vec<Coord2D>,
+	pub blob_spawns: Vec<Coord2D>,
+	pub tiles: Vec<(Coord2D, Tile)>,
+}
+
+#[derive(Debug)]
+pub enum MapError {
+	Io(io::Error),
+	Empty,
+	RaggedRow { row: usize },
+	UnknownChar { row: usize, column: usize, found: char },
+	UnpairedPortal(char),
+	TooManyPortals,
+	BadSize { width: u16, height: u16 },
+	BadGenerator(MapGen),
+	OutOfBounds(Coord2D),
+	OpenBoundary(Coord2D),
+	WallInBase(Coord2D),
+	SpawnInWall(Coord2D),
+	NoReachableSpawn,
+}
+impl From<io::Error> for MapError {
+	fn from(e: io::Error) -> Self {
+		MapError::Io(e)
+	}
+}
+impl fmt::Display for MapError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		use self::MapError::*;
+		match *self {
+			Io(ref e) => write!(f, "couldn't read map: {}", e),
+			Empty => write!(f, "map has no rows"),
+			RaggedRow { row } => write!(f, "row {} isn't as long as the first", row),
+			UnknownChar { row, column, found } =>
+				write!(f, "unknown cell `{}` at row {}, column {}", found, row, column),
+			UnpairedPortal(c) => write!(f, "portal `{}` needs exactly two cells", c),
+			TooManyPortals => write!(f, "at most 9 portal pairs fit in a map file"),
+			BadSize { width, height } => write!(
+				f, "map is {}x{}. it must be between {}x{} and {}x{}",
+				width, height,
+				MapFile::MIN_SIZE, MapFile::MIN_SIZE,
+				MapFile::MAX_SIZE, MapFile::MAX_SIZE,
+			),
+			BadGenerator(ref gen) => write!(f, "can't generate a map with {:?}", gen),
+			OutOfBounds(c) => write!(f, "({}, {}) is outside the map", c.x, c.y),
+			OpenBoundary(c) => write!(f, "boundary cell ({}, {}) must be a wall", c.x, c.y),
+			WallInBase(c) => write!(f, "wall at ({}, {}) is inside a base", c.x, c.y),
+			SpawnInWall(c) => write!(f, "spawn point ({}, {}) is inside a wall", c.x, c.y),
+			NoReachableSpawn => write!(f, "no spawn point is connected to a base"),
+		}
+	}
+}
+
+impl MapFile {
+	// big enough that the bases don't touch
+	pub const MIN_SIZE: u16 = 2 * Bounds::BASE_SIZE + 3;
+	pub const MAX_SIZE: u16 = 200;
+
+	// an open floor surrounded by boundary walls
+	pub fn empty(width: u16, height: u16) -> MapFile {
+		let bounds = Bounds::new(width, height);
+		MapFile {
+			width,
+			height,
+			walls: bounds.coord_iter().filter(|&c| bounds.on_boundary(c)).collect(),
+			spawn_points: vec![],
+			blob_spawns: vec![],
+			tiles: vec![],
+		}
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<MapFile, MapError> {
+		let map = MapFile::read(path)?;
+		map.validate()?;
+		Ok(map)
+	}
+
+	// like `load`, but doesn't validate. for maps that are still being worked on
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<MapFile, MapError> {
+		let text = fs::read_to_string(path)?;
+		MapFile::parse(&text)
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		fs::write(path, self.to_text())
+	}
+
+	pub fn parse(text: &str) -> Result<MapFile, MapError> {
+		let rows: Vec<&str> = text.lines()
+			.map(|line| line.trim_end())
+			.filter(|line| !line.is_empty() && !line.starts_with("//"))
+			.collect();
+		if rows.is_empty() {
+			return Err(MapError::Empty);
+		}
+		let width = rows[0].chars().count();
+		let mut map = MapFile {
+			width: width as u16,
+			height: rows.len() as u16,
+			walls: vec![],
+			spawn_points: vec![],
+			blob_spawns: vec![],
+			tiles: vec![],
+		};
+		if width > MapFile::MAX_SIZE as usize || rows.len() > MapFile::MAX_SIZE as usize {
+			return Err(MapError::BadSize { width: map.width, height: map.height });
+		}
+		let mut portals: HashMap<char, Vec<Coord2D>> = HashMap::new();
+		for (y, row) in rows.iter().enumerate() {
+			if row.chars().count() != width {
+				return Err(MapError::RaggedRow { row: y });
+			}
+			for (x, c) in row.chars().enumerate() {
+				let coord = Coord2D::new(x as u16, y as u16);
+				match c {
+					'#' => map.walls.push(coord),
+					'.' => (),
+					'S' => map.spawn_points.push(coord),
+					'*' => map.blob_spawns.push(coord),
+					'1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' =>
+						portals.entry(c).or_default().push(coord),
+					'^' => map.tiles.push((coord, Tile::Conveyor(Direction::Up))),
+					'v' => map.tiles.push((coord, Tile::Conveyor(Direction::Down))),
+					'<' => map.tiles.push((coord, Tile::Conveyor(Direction::Left))),
+					'>' => map.tiles.push((coord, Tile::Conveyor(Direction::Right))),
+					'~' => map.tiles.push((coord, Tile::Ice)),
+					'x' => map.tiles.push((coord, Tile::Pit)),
+					found => return Err(MapError::UnknownChar { row: y, column: x, found }),
+				}
+			}
+		}
+		let mut portal_chars: Vec<char> = portals.keys().cloned().collect();
+		portal_chars.sort();
+		for c in portal_chars {
+			let ends = &portals[&c];
+			if ends.len() != 2 {
+				return Err(MapError::UnpairedPortal(c));
+			}
+			map.tiles.push((ends[0], Tile::Portal(ends[1])));
+			map.tiles.push((ends[1], Tile::Portal(ends[0])));
+		}
+		Ok(map)
+	}
+
+	pub fn to_text(&self) -> String {
+		let mut cells = vec![vec!['.'; self.width as usize]; self.height as usize];
+		for &c in self.walls.iter() {
+			cells[c.y as usize][c.x as usize] = '#';
+		}
+		for &c in self.spawn_points.iter() {
+			cells[c.y as usize][c.x as usize] = 'S';
+		}
+		for &c in self.blob_spawns.iter() {
+			cells[c.y as usize][c.x as usize] = '*';
+		}
+		let mut next_portal = b'1';
+		for &(c, tile) in self.tiles.iter() {
+			cells[c.y as usize][c.x as usize] = match tile {
+				Tile::Portal(partner) => {
+					let placed = cells[partner.y as usize][partner.x as usize];
+					if ('1'..='9').contains(&placed) {
+						placed // partner already got a digit
+					} else {
+						next_portal += 1;
+						(next_portal - 1) as char
+					}
+				},
+				Tile::Conveyor(Direction::Up) => '^',
+				Tile::Conveyor(Direction::Down) => 'v',
+				Tile::Conveyor(Direction::Left) => '<',
+				Tile::Conveyor(Direction::Right) => '>',
+				Tile::Ice => '~',
+				Tile::Pit => 'x',
+			};
+		}
+		let mut text = String::new();
+		for row in cells {
+			text.extend(row);
+			text.push('\n');
+		}
+		text
+	}
+
+	pub fn validate(&self) -> Result<(), MapError> {
+		self.validate_shape()?;
+		if self.portal_pairs() > 9 {
+			return Err(MapError::TooManyPortals);
+		}
+		let bounds = self.bounds();
+		let grid = self.wall_grid();
+		for coord in bounds.coord_iter() {
+			let wall = mapgen::is_wall(&grid, coord.x, coord.y);
+			if bounds.on_boundary(coord) && !wall {
+				return Err(MapError::OpenBoundary(coord));
+			}
+			if bounds.base_at(coord).is_some() && wall {
+				return Err(MapError::WallInBase(coord));
+			}
+		}
+		for &coord in self.spawn_points.iter() {
+			if mapgen::is_wall(&grid, coord.x, coord.y) {
+				return Err(MapError::SpawnInWall(coord));
+			}
+		}
+		// a spawn point is reachable if you can walk from it to a base
+		let from_red = mapgen::reachable(&grid, bounds, bounds.base_corner(Team::Red));
+		let from_blue = mapgen::reachable(&grid, bounds, bounds.base_corner(Team::Blue));
+		let any_reachable = self.spawn_points.iter().any(|&coord| {
+			let i = bounds.index_of(coord);
+			from_red[i] || from_blue[i]
+		});
+		if !any_reachable {
+			return Err(MapError::NoReachableSpawn);
+		}
+		Ok(())
+	}
+
+	// generated maps are held to the same sizes
+	pub fn check_size(bounds: Bounds) -> Result<(), MapError> {
+		if bounds.width < MapFile::MIN_SIZE || bounds.height < MapFile::MIN_SIZE
+		|| bounds.width > MapFile::MAX_SIZE || bounds.height > MapFile::MAX_SIZE {
+			return Err(MapError::BadSize { width: bounds.width, height: bounds.height });
+		}
+		Ok(())
+	}
+
+	// what a game can be built from: a size in range, with everything on the map.
+	// validate also wants it playable
+	pub fn validate_shape(&self) -> Result<(), MapError> {
+		let bounds = self.bounds();
+		MapFile::check_size(bounds)?;
+		let partners = self.tiles.iter().filter_map(|&(_, tile)| match tile {
+			Tile::Portal(partner) => Some(partner),
+			_ => None,
+		});
+		let all_coords = self.walls.iter().cloned()
+			.chain(self.spawn_points.iter().cloned())
+			.chain(self.blob_spawns.iter().cloned())
+			.chain(self.tiles.iter().map(|&(c, _)| c))
+			.chain(partners);
+		for coord in all_coords {
+			if !bounds.contains(coord) {
+				return Err(MapError::OutOfBounds(coord));
+			}
+		}
+		Ok(())
+	}
+
+	#[inline]
+	pub fn bounds(&self) -> Bounds {
+		Bounds::new(self.width, self.height)
+	}
+
+	pub fn wall_grid(&self) -> WallGrid {
+		let mut grid = mapgen::new_grid(self.width, self.height, false);
+		for &c in self.walls.iter() {
+			mapgen::set_wall(&mut grid, c.x, c.y, true);
+		}
+		grid
+	}
+
+	pub fn tile_map(&self) -> HashMap<Coord2D, Tile> {
+		self.tiles.iter().cloned().collect()
+	}
+
+	pub fn portal_pairs(&self) -> usize {
+		self.tiles.iter()
+			.filter(|&&(_, tile)| matches!(tile, Tile::Portal(_)))
+			.count() / 2
+	}
+
+	// removes whatever is at `coord`. a portal takes its partner with it
+	pub fn clear_cell(&mut self, coord: Coord2D) {
+		self.walls.retain(|&c| c != coord);
+		self.spawn_points.retain(|&c| c != coord);
+		self.blob_spawns.retain(|&c| c != coord);
+		let partner = self.tiles.iter()
+			.filter_map(|&(c, tile)| match tile {
+				Tile::Portal(p) if c == coord => Some(p),
+				_ => None,
+			})
+			.next();
+		self.tiles.retain(|&(c, _)| c != coord && Some(c) != partner);
+	}
+}
@@ -0,0 +1,368 @@
+use ::game::*;
+use rand::{
+	Rng,
+	XorShiftRng,
+};
+use grid::BitGrid;
+use mapfile::{
+	MapError,
+	MapFile,
+};
+use std::{
+	str::FromStr,
+	collections::VecDeque,
+};
+
+/////////////////////////////////////////////////////////////
+
+// set bits are walls
+pub type WallGrid = BitGrid;
+
+pub trait MapGenerator {
+	// must draw all its randomness from `rng`, as every client regenerates the map
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid;
+}
+
+#[inline]
+pub fn new_grid(width: u16, height: u16, wall: bool) -> WallGrid {
+	BitGrid::new(width, height, wall)
+}
+
+#[inline]
+pub fn is_wall(grid: &WallGrid, x: u16, y: u16) -> bool {
+	grid.get(x, y)
+}
+
+#[inline]
+pub fn set_wall(grid: &mut WallGrid, x: u16, y: u16, wall: bool) {
+	grid.set(x, y, wall);
+}
+
+/////////////////////////////////////////////////////////////
+
+// every cell is a wall with chance 1/wall_one_in. the original generator
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Noise {
+	pub wall_one_in: u32,
+}
+impl Default for Noise {
+	fn default() -> Self {
+		Noise { wall_one_in: 3 }
+	}
+}
+impl MapGenerator for Noise {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		let mut grid = new_grid(width, height, false);
+		for y in 0..height {
+			for x in 0..width {
+				if rng.gen_weighted_bool(self.wall_one_in) {
+					set_wall(&mut grid, x, y, true);
+				}
+			}
+		}
+		grid
+	}
+}
+
+// random fill, smoothed by cellular automaton into blobby caves
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Caves {
+	pub fill_percent: u8,
+	pub smoothing_steps: u8,
+}
+impl Default for Caves {
+	fn default() -> Self {
+		Caves { fill_percent: 45, smoothing_steps: 4 }
+	}
+}
+impl MapGenerator for Caves {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		let mut grid = new_grid(width, height, false);
+		for y in 0..height {
+			for x in 0..width {
+				if rng.gen_range(0, 100) < self.fill_percent {
+					set_wall(&mut grid, x, y, true);
+				}
+			}
+		}
+		for _ in 0..self.smoothing_steps {
+			let mut next = grid.clone();
+			for y in 0..height {
+				for x in 0..width {
+					let walls = wall_neighbours(&grid, x, y, width, height);
+					if walls > 4 {
+						set_wall(&mut next, x, y, true);
+					} else if walls < 4 {
+						set_wall(&mut next, x, y, false);
+					}
+				}
+			}
+			grid = next;
+		}
+		grid
+	}
+}
+
+// of the 8 surrounding cells. off the map counts as wall
+fn wall_neighbours(grid: &WallGrid, x: u16, y: u16, width: u16, height: u16) -> u8 {
+	let mut count = 0;
+	for dy in -1i32..2 {
+		for dx in -1i32..2 {
+			if dx == 0 && dy == 0 { continue; }
+			let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+			if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32
+			|| is_wall(grid, nx as u16, ny as u16) {
+				count += 1;
+			}
+		}
+	}
+	count
+}
+
+// recursive backtracker on odd coordinates. some walls get knocked out again
+// with chance 1/loop_one_in so there's more than one way around (0 for none)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Maze {
+	pub loop_one_in: u32,
+}
+impl Default for Maze {
+	fn default() -> Self {
+		Maze { loop_one_in: 6 }
+	}
+}
+impl MapGenerator for Maze {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		let mut grid = new_grid(width, height, true);
+		if width < 3 || height < 3 {
+			return grid;
+		}
+		set_wall(&mut grid, 1, 1, false);
+		let mut stack = vec![(1u16, 1u16)];
+		while let Some(&(x, y)) = stack.last() {
+			let mut options = vec![];
+			if x >= 3 && is_wall(&grid, x-2, y) { options.push((x-2, y)); }
+			if y >= 3 && is_wall(&grid, x, y-2) { options.push((x, y-2)); }
+			if x+2 < width-1 && is_wall(&grid, x+2, y) { options.push((x+2, y)); }
+			if y+2 < height-1 && is_wall(&grid, x, y+2) { options.push((x, y+2)); }
+			match rng.choose(&options).cloned() {
+				Some((nx, ny)) => {
+					set_wall(&mut grid, (x+nx)/2, (y+ny)/2, false);
+					set_wall(&mut grid, nx, ny, false);
+					stack.push((nx, ny));
+				},
+				None => { stack.pop(); },
+			}
+		}
+		if self.loop_one_in > 0 {
+			for y in 1..height-1 {
+				for x in 1..width-1 {
+					if !is_wall(&grid, x, y) { continue; }
+					let horizontal = !is_wall(&grid, x-1, y) && !is_wall(&grid, x+1, y);
+					let vertical = !is_wall(&grid, x, y-1) && !is_wall(&grid, x, y+1);
+					if (horizontal || vertical)
+					&& rng.gen_weighted_bool(self.loop_one_in) {
+						set_wall(&mut grid, x, y, false);
+					}
+				}
+			}
+		}
+		grid
+	}
+}
+
+// non-overlapping rectangular rooms, each joined to the last by an L-shaped corridor
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rooms {
+	pub max_rooms: u8,
+	pub min_size: u16,
+	pub max_size: u16,
+}
+impl Default for Rooms {
+	fn default() -> Self {
+		Rooms { max_rooms: 8, min_size: 3, max_size: 7 }
+	}
+}
+impl MapGenerator for Rooms {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		let mut grid = new_grid(width, height, true);
+		let mut rooms: Vec<(u16, u16, u16, u16)> = vec![]; // x, y, w, h
+		for _ in 0..(self.max_rooms as u16 * 4) {
+			if rooms.len() >= self.max_rooms as usize { break; }
+			let w = rng.gen_range(self.min_size, self.max_size + 1);
+			let h = rng.gen_range(self.min_size, self.max_size + 1);
+			if w + 2 >= width || h + 2 >= height { continue; }
+			let x = rng.gen_range(1, width - w);
+			let y = rng.gen_range(1, height - h);
+			// keep a wall between rooms
+			let overlaps = rooms.iter().any(|&(rx, ry, rw, rh)| {
+				x <= rx + rw && rx <= x + w && y <= ry + rh && ry <= y + h
+			});
+			if overlaps { continue; }
+			for cy in y..(y+h) {
+				for cx in x..(x+w) {
+					set_wall(&mut grid, cx, cy, false);
+				}
+			}
+			if let Some(&(px, py, pw, ph)) = rooms.last() {
+				let from = Coord2D::new(px + pw/2, py + ph/2);
+				let to = Coord2D::new(x + w/2, y + h/2);
+				let bend_x = if rng.gen() { from.x } else { to.x };
+				carve_l_path(&mut grid, from, to, bend_x);
+			}
+			rooms.push((x, y, w, h));
+		}
+		grid
+	}
+}
+
+// noise on one half, mirrored onto the other half through the centre.
+// the bases sit in opposite corners, so both teams get the same map
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Arena {
+	pub wall_one_in: u32,
+}
+impl Default for Arena {
+	fn default() -> Self {
+		Arena { wall_one_in: 4 }
+	}
+}
+impl MapGenerator for Arena {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		let mut grid = new_grid(width, height, false);
+		let total = width as usize * height as usize;
+		for y in 0..height {
+			for x in 0..width {
+				let index = y as usize * width as usize + x as usize;
+				let wall = if index <= total - 1 - index {
+					rng.gen_weighted_bool(self.wall_one_in)
+				} else {
+					is_wall(&grid, width-1-x, height-1-y)
+				};
+				set_wall(&mut grid, x, y, wall);
+			}
+		}
+		grid
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+// the generator and its parameters. travels in the essence
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MapGen {
+	Noise(Noise),
+	Caves(Caves),
+	Maze(Maze),
+	Rooms(Rooms),
+	Arena(Arena),
+}
+impl MapGen {
+	pub const NAMES: [&'static str; 5] = ["noise", "caves", "maze", "rooms", "arena"];
+
+	// parameters come off the wire too. the others can't go wrong
+	pub fn validate(&self) -> Result<(), MapError> {
+		match *self {
+			MapGen::Rooms(Rooms { min_size, max_size, .. })
+			if min_size > max_size || max_size > MapFile::MAX_SIZE => Err(MapError::BadGenerator(*self)),
+			_ => Ok(()),
+		}
+	}
+}
+impl Default for MapGen {
+	fn default() -> Self {
+		MapGen::Noise(Noise::default())
+	}
+}
+impl FromStr for MapGen {
+	type Err = ();
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_ref() {
+			"noise" => Ok(MapGen::Noise(Noise::default())),
+			"caves" => Ok(MapGen::Caves(Caves::default())),
+			"maze" => Ok(MapGen::Maze(Maze::default())),
+			"rooms" => Ok(MapGen::Rooms(Rooms::default())),
+			"arena" => Ok(MapGen::Arena(Arena::default())),
+			_ => Err(()),
+		}
+	}
+}
+impl MapGenerator for MapGen {
+	fn generate(&self, rng: &mut XorShiftRng, width: u16, height: u16) -> WallGrid {
+		match *self {
+			MapGen::Noise(ref g) => g.generate(rng, width, height),
+			MapGen::Caves(ref g) => g.generate(rng, width, height),
+			MapGen::Maze(ref g) => g.generate(rng, width, height),
+			MapGen::Rooms(ref g) => g.generate(rng, width, height),
+			MapGen::Arena(ref g) => g.generate(rng, width, height),
+		}
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+// generates with `gen`, then walls the boundary, clears the bases and makes
+// sure every open cell can be walked to from both bases
+pub fn generate_map(gen: &MapGen, rng: &mut XorShiftRng, bounds: Bounds) -> WallGrid {
+	let (width, height) = (bounds.width, bounds.height);
+	let mut grid = gen.generate(rng, width, height);
+	for coord in bounds.coord_iter() {
+		if bounds.on_boundary(coord) {
+			set_wall(&mut grid, coord.x, coord.y, true);
+		} else if bounds.base_at(coord).is_some() {
+			set_wall(&mut grid, coord.x, coord.y, false);
+		}
+	}
+	let half_base = Bounds::BASE_SIZE / 2;
+	let red_anchor = Coord2D::new(half_base, half_base);
+	let blue_anchor = Coord2D::new(width-1-half_base, height-1-half_base);
+	let reached = reachable(&grid, bounds, red_anchor);
+	if !reached[bounds.index_of(blue_anchor)] {
+		let bend_x = rng.gen_range(1, width-1);
+		carve_l_path(&mut grid, red_anchor, blue_anchor, bend_x);
+	}
+	// seal off the pockets nobody could spawn into and get out of
+	let reached = reachable(&grid, bounds, red_anchor);
+	for coord in bounds.coord_iter() {
+		if !reached[bounds.index_of(coord)] {
+			set_wall(&mut grid, coord.x, coord.y, true);
+		}
+	}
+	grid
+}
+
+// open cells connected to `start`, indexed by Bounds::index_of
+pub fn reachable(grid: &WallGrid, bounds: Bounds, start: Coord2D) -> Vec<bool> {
+	let mut reached = vec![false; bounds.total_coords()];
+	if is_wall(grid, start.x, start.y) {
+		return reached;
+	}
+	let mut frontier = VecDeque::new();
+	reached[bounds.index_of(start)] = true;
+	frontier.push_back(start);
+	while let Some(coord) = frontier.pop_front() {
+		for &dir in Direction::ALL.iter() {
+			if bounds.would_exit(coord, dir) { continue; }
+			let next = coord.move_with(dir);
+			let i = bounds.index_of(next);
+			if !reached[i] && !is_wall(grid, next.x, next.y) {
+				reached[i] = true;
+				frontier.push_back(next);
+			}
+		}
+	}
+	reached
+}
+
+// horizontally to `bend_x`, vertically to `to.y`, then horizontally to `to`
+fn carve_l_path(grid: &mut WallGrid, from: Coord2D, to: Coord2D, bend_x: u16) {
+	let span = |a: u16, b: u16| if a <= b { a..(b+1) } else { b..(a+1) };
+	for x in span(from.x, bend_x) {
+		set_wall(grid, x, from.y, false);
+	}
+	for y in span(from.y, to.y) {
+		set_wall(grid, bend_x, y, false);
+	}
+	for x in span(bend_x, to.x) {
+		set_wall(grid, x, to.y, false);
+	}
+}
@@ -0,0 +1,358 @@
+use ::common::*;
+use ::game::Moniker;
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{
+		self,
+		Read,
+		Write,
+	},
+	net::{
+		SocketAddr,
+		TcpListener,
+	},
+	sync::{
+		Arc,
+		Mutex,
+	},
+	thread,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+pub const DEFAULT_CSV_PERIOD: Duration = Duration::from_secs(10);
+// upper bounds of the tick duration histogram, in seconds
+const TICK_BUCKET_COUNT: usize = 8;
+const TICK_BUCKETS: [f32; TICK_BUCKET_COUNT] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05];
+const SCRAPE_TIMEOUT: Duration = Duration::from_millis(100);
+const PUBLISH_PERIOD: Duration = Duration::from_secs(1); // how stale a scrape can be
+
+/*
+Counts what the server gets up to, for charting playtests. Everything is
+counted from when the server started. `render_prometheus` gives the lot in
+Prometheus' text format, and `csv_row` a summary line for the CSV dump.
+*/
+#[derive(Debug)]
+pub struct Metrics {
+	started: Instant,
+	connections: u64,
+	handshakes: HashMap<&'static str, u64>, // by outcome
+	sent: HashMap<&'static str, u64>, // by Clientward kind
+	received: HashMap<&'static str, u64>, // by Serverward kind
+	bytes_sent: u64,
+	bytes_received: u64,
+	dropped_moves: HashMap<&'static str, u64>, // by reason
+	dropped_chats: HashMap<&'static str, u64>, // by reason
+	dropped_marks: HashMap<&'static str, u64>, // by reason
+	tick_buckets: [u64; TICK_BUCKET_COUNT],
+	tick_count: u64,
+	tick_sum: f64,
+	tick_max_since_row: f32,
+	client_rtts: HashMap<Moniker, Duration>,
+	clients: usize,
+	newcomers: usize,
+}
+impl Metrics {
+	pub fn new() -> Self {
+		Metrics {
+			started: Instant::now(),
+			connections: 0,
+			handshakes: HashMap::new(),
+			sent: HashMap::new(),
+			received: HashMap::new(),
+			bytes_sent: 0,
+			bytes_received: 0,
+			dropped_moves: HashMap::new(),
+			dropped_chats: HashMap::new(),
+			dropped_marks: HashMap::new(),
+			tick_buckets: [0; TICK_BUCKET_COUNT],
+			tick_count: 0,
+			tick_sum: 0.0,
+			tick_max_since_row: 0.0,
+			client_rtts: HashMap::new(),
+			clients: 0,
+			newcomers: 0,
+		}
+	}
+
+	pub fn on_connection(&mut self) {
+		self.connections += 1;
+	}
+
+	// `outcome` is "welcomed" or why the newcomer was turned away
+	pub fn on_handshake(&mut self, outcome: &'static str) {
+		*self.handshakes.entry(outcome).or_insert(0) += 1;
+	}
+
+	pub fn on_sent(&mut self, msg: &Clientward, bytes: usize) {
+		*self.sent.entry(msg.kind()).or_insert(0) += 1;
+		self.bytes_sent += bytes as u64;
+	}
+
+	pub fn on_received(&mut self, msg: &Serverward, bytes: usize) {
+		*self.received.entry(msg.kind()).or_insert(0) += 1;
+		self.bytes_received += bytes as u64;
+	}
+
+	pub fn on_dropped_move(&mut self, reason: &'static str) {
+		*self.dropped_moves.entry(reason).or_insert(0) += 1;
+	}
+
+	pub fn on_dropped_chat(&mut self, reason: &'static str) {
+		*self.dropped_chats.entry(reason).or_insert(0) += 1;
+	}
+
+	pub fn on_dropped_mark(&mut self, reason: &'static str) {
+		*self.dropped_marks.entry(reason).or_insert(0) += 1;
+	}
+
+	pub fn on_tick(&mut self, duration: Duration) {
+		let secs = secs_f32(duration);
+		for (count, &bound) in self.tick_buckets.iter_mut().zip(TICK_BUCKETS.iter()) {
+			if secs <= bound {
+				*count += 1;
+			}
+		}
+		self.tick_count += 1;
+		self.tick_sum += secs as f64;
+		self.tick_max_since_row = self.tick_max_since_row.max(secs);
+	}
+
+	pub fn on_client_rtt(&mut self, moniker: Moniker, rtt: Duration) {
+		self.client_rtts.insert(moniker, rtt);
+	}
+
+	pub fn forget_client(&mut self, moniker: Moniker) {
+		self.client_rtts.remove(&moniker);
+	}
+
+	pub fn set_population(&mut self, clients: usize, newcomers: usize) {
+		self.clients = clients;
+		self.newcomers = newcomers;
+	}
+
+	pub fn render_prometheus(&self) -> String {
+		let mut out = String::new();
+		metric(&mut out, "pinggame_uptime_seconds", "gauge", "Seconds since the server started.");
+		out.push_str(&format!("pinggame_uptime_seconds {}\n", secs_f32(self.started.elapsed())));
+		metric(&mut out, "pinggame_connections_total", "counter", "TCP connections accepted.");
+		out.push_str(&format!("pinggame_connections_total {}\n", self.connections));
+		metric(&mut out, "pinggame_clients", "gauge", "Players connected, and connections yet to say hello.");
+		out.push_str(&format!("pinggame_clients{{state=\"playing\"}} {}\n", self.clients));
+		out.push_str(&format!("pinggame_clients{{state=\"newcomer\"}} {}\n", self.newcomers));
+		metric(&mut out, "pinggame_handshakes_total", "counter", "Finished handshakes, by outcome.");
+		for (outcome, count) in sorted(&self.handshakes) {
+			out.push_str(&format!("pinggame_handshakes_total{{outcome=\"{}\"}} {}\n", outcome, count));
+		}
+		metric(&mut out, "pinggame_messages_sent_total", "counter", "Messages sent to clients, by kind.");
+		for kind in Clientward::KINDS.iter() {
+			out.push_str(&format!("pinggame_messages_sent_total{{kind=\"{}\"}} {}\n",
+				kind, self.sent.get(kind).cloned().unwrap_or(0)));
+		}
+		metric(&mut out, "pinggame_messages_received_total", "counter", "Messages received from clients, by kind.");
+		for kind in Serverward::KINDS.iter() {
+			out.push_str(&format!("pinggame_messages_received_total{{kind=\"{}\"}} {}\n",
+				kind, self.received.get(kind).cloned().unwrap_or(0)));
+		}
+		metric(&mut out, "pinggame_bytes_total", "counter", "Message bytes, by direction.");
+		out.push_str(&format!("pinggame_bytes_total{{direction=\"sent\"}} {}\n", self.bytes_sent));
+		out.push_str(&format!("pinggame_bytes_total{{direction=\"received\"}} {}\n", self.bytes_received));
+		metric(&mut out, "pinggame_dropped_moves_total", "counter", "Move requests ignored, by reason.");
+		for (reason, count) in sorted(&self.dropped_moves) {
+			out.push_str(&format!("pinggame_dropped_moves_total{{reason=\"{}\"}} {}\n", reason, count));
+		}
+		metric(&mut out, "pinggame_dropped_chats_total", "counter", "Chat messages not passed on, by reason.");
+		for (reason, count) in sorted(&self.dropped_chats) {
+			out.push_str(&format!("pinggame_dropped_chats_total{{reason=\"{}\"}} {}\n", reason, count));
+		}
+		metric(&mut out, "pinggame_dropped_marks_total", "counter", "Map pings not passed on, by reason.");
+		for (reason, count) in sorted(&self.dropped_marks) {
+			out.push_str(&format!("pinggame_dropped_marks_total{{reason=\"{}\"}} {}\n", reason, count));
+		}
+		metric(&mut out, "pinggame_tick_duration_seconds", "histogram", "Time spent on each pass of the server loop.");
+		for (count, bound) in self.tick_buckets.iter().zip(TICK_BUCKETS.iter()) {
+			out.push_str(&format!("pinggame_tick_duration_seconds_bucket{{le=\"{}\"}} {}\n", bound, count));
+		}
+		out.push_str(&format!("pinggame_tick_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", self.tick_count));
+		out.push_str(&format!("pinggame_tick_duration_seconds_sum {}\n", self.tick_sum));
+		out.push_str(&format!("pinggame_tick_duration_seconds_count {}\n", self.tick_count));
+		metric(&mut out, "pinggame_client_rtt_seconds", "gauge", "Each player's latest round trip, as they reported it.");
+		let mut rtts: Vec<_> = self.client_rtts.iter().collect();
+		rtts.sort_by_key(|&(m, _)| m.0);
+		for (moniker, &rtt) in rtts {
+			out.push_str(&format!("pinggame_client_rtt_seconds{{moniker=\"{}\"}} {}\n",
+				escape_label(moniker.0), secs_f32(rtt)));
+		}
+		out
+	}
+
+	pub fn csv_header() -> String {
+		let mut cols = vec![
+			"uptime_s".to_owned(), "connections".to_owned(), "clients".to_owned(),
+			"newcomers".to_owned(), "handshakes_ok".to_owned(), "handshakes_failed".to_owned(),
+		];
+		cols.extend(Clientward::KINDS.iter().map(|k| format!("sent_{}", k)));
+		cols.extend(Serverward::KINDS.iter().map(|k| format!("received_{}", k)));
+		cols.extend(["bytes_sent", "bytes_received", "dropped_moves", "dropped_chats", "dropped_marks",
+			"ticks", "tick_mean_ms", "tick_max_ms", "rtt_mean_ms", "rtt_max_ms"].iter().map(|s| s.to_string()));
+		cols.join(",")
+	}
+
+	// one line of the CSV dump. the tick maximum covers the time since the last row
+	pub fn csv_row(&mut self) -> String {
+		let welcomed = self.handshakes.get("welcomed").cloned().unwrap_or(0);
+		let handshakes: u64 = self.handshakes.values().sum();
+		let mut cols = vec![
+			format!("{:.1}", secs_f32(self.started.elapsed())),
+			self.connections.to_string(),
+			self.clients.to_string(),
+			self.newcomers.to_string(),
+			welcomed.to_string(),
+			(handshakes - welcomed).to_string(),
+		];
+		cols.extend(Clientward::KINDS.iter().map(|k| self.sent.get(k).cloned().unwrap_or(0).to_string()));
+		cols.extend(Serverward::KINDS.iter().map(|k| self.received.get(k).cloned().unwrap_or(0).to_string()));
+		let rtts: Vec<f32> = self.client_rtts.values().map(|&d| secs_f32(d) * 1000.0).collect();
+		let tick_mean = if self.tick_count == 0 { 0.0 } else { self.tick_sum / self.tick_count as f64 };
+		let rtt_mean = if rtts.is_empty() { 0.0 } else { rtts.iter().sum::<f32>() / rtts.len() as f32 };
+		cols.extend(vec![
+			self.bytes_sent.to_string(),
+			self.bytes_received.to_string(),
+			self.dropped_moves.values().sum::<u64>().to_string(),
+			self.dropped_chats.values().sum::<u64>().to_string(),
+			self.dropped_marks.values().sum::<u64>().to_string(),
+			self.tick_count.to_string(),
+			format!("{:.3}", tick_mean * 1000.0),
+			format!("{:.3}", self.tick_max_since_row * 1000.0),
+			format!("{:.1}", rtt_mean),
+			format!("{:.1}", rtts.iter().cloned().fold(0.0, f32::max)),
+		]);
+		self.tick_max_since_row = 0.0;
+		cols.join(",")
+	}
+}
+impl Default for Metrics {
+	fn default() -> Self {
+		Metrics::new()
+	}
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+	out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+}
+
+fn sorted(counts: &HashMap<&'static str, u64>) -> Vec<(&'static str, u64)> {
+	let mut v: Vec<_> = counts.iter().map(|(&k, &v)| (k, v)).collect();
+	v.sort();
+	v
+}
+
+fn escape_label(c: char) -> String {
+	match c {
+		'\\' => "\\\\".to_owned(),
+		'"' => "\\\"".to_owned(),
+		'\n' => "\\n".to_owned(),
+		c => c.to_string(),
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+/*
+A tiny HTTP server for Prometheus to scrape. It runs on a thread of its own
+until the process ends, and answers every request, whatever the path, with the
+metrics as the game loop last published them. A slow scraper only holds up
+other scrapers, for up to SCRAPE_TIMEOUT each, and never the game.
+*/
+pub struct MetricsEndpoint {
+	addr: SocketAddr,
+	published: Arc<Mutex<String>>,
+	last_published_at: Option<Instant>,
+}
+impl MetricsEndpoint {
+	pub fn bind(addr: &SocketAddr) -> io::Result<Self> {
+		let listener = TcpListener::bind(addr)?;
+		let published = Arc::new(Mutex::new(String::new()));
+		let endpoint = MetricsEndpoint {
+			addr: listener.local_addr()?,
+			published: published.clone(),
+			last_published_at: None,
+		};
+		thread::Builder::new().name("metrics".to_owned()).spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => {
+						let body = published.lock().unwrap().clone();
+						if let Err(e) = respond(stream, &body) {
+							debug!("metrics scrape failed err={}", e);
+						}
+					},
+					Err(e) => warn!("metrics accept failed err={}", e),
+				}
+			}
+		})?;
+		Ok(endpoint)
+	}
+
+	pub fn local_addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	// renders the metrics for scrapers to pick up, at most every PUBLISH_PERIOD
+	pub fn maybe_publish(&mut self, metrics: &Metrics, now: Instant) {
+		if let Some(at) = self.last_published_at {
+			if now.duration_since(at) < PUBLISH_PERIOD {
+				return;
+			}
+		}
+		self.last_published_at = Some(now);
+		let body = metrics.render_prometheus();
+		*self.published.lock().unwrap() = body;
+	}
+}
+
+fn respond(mut stream: StdStream, body: &str) -> io::Result<()> {
+	stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+	stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+	let mut request = [0u8; 1024];
+	let _ = stream.read(&mut request)?; // whatever they asked, they get the metrics
+	write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		body.len(), body)?;
+	stream.flush()
+}
+
+/////////////////////////////////////////////////////////////
+
+// appends a row of `Metrics::csv_row` every `period`
+pub struct CsvDump {
+	file: File,
+	period: Duration,
+	last_row_at: Instant,
+}
+impl CsvDump {
+	pub fn create(path: &str, period: Duration) -> io::Result<Self> {
+		let mut file = File::create(path)?;
+		writeln!(file, "{}", Metrics::csv_header())?;
+		Ok(CsvDump {
+			file,
+			period,
+			last_row_at: Instant::now(),
+		})
+	}
+
+	pub fn maybe_dump(&mut self, metrics: &mut Metrics) {
+		if self.last_row_at.elapsed() < self.period {
+			return;
+		}
+		self.last_row_at = Instant::now();
+		if let Err(e) = writeln!(self.file, "{}", metrics.csv_row()) {
+			warn!("metrics csv write failed err={}", e);
+		}
+	}
+}
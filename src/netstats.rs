@@ -0,0 +1,161 @@
+use ::common::secs_f32;
+
+use std::{
+	collections::VecDeque,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+pub const PING_PERIOD: Duration = Duration::from_millis(500);
+pub const RTT_HISTORY: usize = 60; // samples kept for the average, jitter and graph
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_PINGS_IN_FLIGHT: usize = 16; // unanswered ones past this are given up on
+
+// messages and bytes over the last RATE_WINDOW
+#[derive(Debug, Default)]
+struct RateCounter {
+	window: VecDeque<(Instant, usize)>,
+}
+impl RateCounter {
+	fn record(&mut self, bytes: usize, now: Instant) {
+		self.window.push_back((now, bytes));
+		self.expire(now);
+	}
+
+	fn expire(&mut self, now: Instant) {
+		while let Some(&(at, _)) = self.window.front() {
+			if now.duration_since(at) <= RATE_WINDOW {
+				break;
+			}
+			self.window.pop_front();
+		}
+	}
+
+	// (messages, bytes) per second
+	fn per_second(&self) -> (usize, usize) {
+		(self.window.len(), self.window.iter().map(|&(_, b)| b).sum())
+	}
+}
+
+/*
+What the client knows about its connection. It pings the server every
+PING_PERIOD, and the server answers with its current tick. Every message in
+and out is counted as it passes.
+*/
+#[derive(Debug)]
+pub struct NetStats {
+	rtts: VecDeque<Duration>,
+	pings_in_flight: VecDeque<(u32, Instant)>,
+	next_ping: u32,
+	last_ping_at: Option<Instant>,
+	server_tick: u64,
+	sent: RateCounter,
+	received: RateCounter,
+}
+impl NetStats {
+	pub fn new() -> Self {
+		NetStats {
+			rtts: VecDeque::with_capacity(RTT_HISTORY),
+			pings_in_flight: VecDeque::new(),
+			next_ping: 0,
+			last_ping_at: None,
+			server_tick: 0,
+			sent: RateCounter::default(),
+			received: RateCounter::default(),
+		}
+	}
+
+	// the id of the ping to send now, if one is due
+	pub fn due_ping(&mut self, now: Instant) -> Option<u32> {
+		if let Some(last) = self.last_ping_at {
+			if now.duration_since(last) < PING_PERIOD {
+				return None;
+			}
+		}
+		let id = self.next_ping;
+		self.next_ping = self.next_ping.wrapping_add(1);
+		self.last_ping_at = Some(now);
+		if self.pings_in_flight.len() >= MAX_PINGS_IN_FLIGHT {
+			self.pings_in_flight.pop_front();
+		}
+		self.pings_in_flight.push_back((id, now));
+		Some(id)
+	}
+
+	pub fn on_pong(&mut self, id: u32, server_tick: u64, now: Instant) {
+		self.server_tick = server_tick;
+		if let Some(at) = self.pings_in_flight.iter().position(|&(x, _)| x == id) {
+			let (_, sent_at) = self.pings_in_flight.drain(..at+1).next_back().unwrap();
+			if self.rtts.len() >= RTT_HISTORY {
+				self.rtts.pop_front();
+			}
+			self.rtts.push_back(now.duration_since(sent_at));
+		}
+	}
+
+	pub fn on_sent(&mut self, bytes: usize, now: Instant) {
+		self.sent.record(bytes, now);
+	}
+
+	pub fn on_received(&mut self, bytes: usize, now: Instant) {
+		self.received.record(bytes, now);
+	}
+
+	// lets the rates drop back down when nothing is coming or going
+	pub fn expire(&mut self, now: Instant) {
+		self.sent.expire(now);
+		self.received.expire(now);
+	}
+
+	#[inline]
+	pub fn server_tick(&self) -> u64 {
+		self.server_tick
+	}
+
+	// oldest first
+	pub fn rtt_history(&self) -> &VecDeque<Duration> {
+		&self.rtts
+	}
+
+	pub fn rtt(&self) -> Option<Duration> {
+		self.rtts.back().cloned()
+	}
+
+	pub fn average_rtt(&self) -> Option<Duration> {
+		if self.rtts.is_empty() {
+			return None;
+		}
+		let total: Duration = self.rtts.iter().fold(Duration::from_millis(0), |a, &b| a + b);
+		Some(total / self.rtts.len() as u32)
+	}
+
+	// mean difference between consecutive round trips, in seconds
+	pub fn jitter(&self) -> Option<f32> {
+		if self.rtts.len() < 2 {
+			return None;
+		}
+		let total: f32 = self.rtts.iter().zip(self.rtts.iter().skip(1))
+			.map(|(&a, &b)| (secs_f32(a) - secs_f32(b)).abs())
+			.sum();
+		Some(total / (self.rtts.len() - 1) as f32)
+	}
+
+	// (messages, bytes) per second
+	pub fn sent_per_second(&self) -> (usize, usize) {
+		self.sent.per_second()
+	}
+
+	// (messages, bytes) per second
+	pub fn received_per_second(&self) -> (usize, usize) {
+		self.received.per_second()
+	}
+}
+impl Default for NetStats {
+	fn default() -> Self {
+		NetStats::new()
+	}
+}
@@ -0,0 +1,170 @@
+use ::game::*;
+
+use std::{
+	cmp::Reverse,
+	collections::{
+		BinaryHeap,
+		HashMap,
+		VecDeque,
+	},
+};
+
+/*
+Reachability and pathfinding over the current board. Paths are plans, not
+promises: ice, conveyors and portals are treated as plain floor (so a path
+over them may carry you further than planned), pits are avoided, and a
+path that pushes a wall doesn't account for where that wall ends up.
+*/
+
+impl GameState { // pathing
+	// what pushing a wall costs on top of the step itself, for astar_path
+	pub const WALL_PUSH_COST: u32 = 3;
+
+	// where a `team` player at `from` ends up stepping in `dir`, if it's a plain step
+	fn free_step(&self, from: Coord2D, dir: Direction, team: Team) -> Option<Coord2D> {
+		if self.coord_would_exit(from, dir) {
+			return None;
+		}
+		let to = from.move_with(dir);
+		if self.is_wall_at(to)
+		|| self.is_player_at(to)
+		|| !self.team_may_enter(team, to) {
+			return None;
+		}
+		match self.tile_at(to) {
+			Some(Tile::Pit) => None,
+			Some(Tile::Conveyor(flow)) if flow == dir.opposite() => None,
+			_ => Some(to),
+		}
+	}
+
+	// every cell a `team` player at `start` can walk to without pushing walls.
+	// indexed by Bounds::index_of
+	pub fn flood_fill(&self, start: Coord2D, team: Team) -> Vec<bool> {
+		let bounds = self.bounds();
+		let mut reached = vec![false; bounds.total_coords()];
+		let mut frontier = VecDeque::new();
+		reached[bounds.index_of(start)] = true;
+		frontier.push_back(start);
+		while let Some(coord) = frontier.pop_front() {
+			for &dir in Direction::ALL.iter() {
+				if let Some(next) = self.free_step(coord, dir, team) {
+					let i = bounds.index_of(next);
+					if !reached[i] {
+						reached[i] = true;
+						frontier.push_back(next);
+					}
+				}
+			}
+		}
+		reached
+	}
+
+	// shortest walk from `from` to the nearest cell satisfying `is_goal`, without pushing walls
+	pub fn bfs_path_to<F>(&self, from: Coord2D, team: Team, is_goal: F) -> Option<Vec<Direction>>
+	where F: Fn(Coord2D) -> bool {
+		let bounds = self.bounds();
+		let mut came_from: Vec<Option<(Coord2D, Direction)>> = vec![None; bounds.total_coords()];
+		let mut seen = vec![false; bounds.total_coords()];
+		let mut frontier = VecDeque::new();
+		seen[bounds.index_of(from)] = true;
+		frontier.push_back(from);
+		while let Some(coord) = frontier.pop_front() {
+			if is_goal(coord) {
+				let mut path = vec![];
+				let mut at = coord;
+				while let Some((prev, dir)) = came_from[bounds.index_of(at)] {
+					path.push(dir);
+					at = prev;
+				}
+				path.reverse();
+				return Some(path);
+			}
+			for &dir in Direction::ALL.iter() {
+				if let Some(next) = self.free_step(coord, dir, team) {
+					let i = bounds.index_of(next);
+					if !seen[i] {
+						seen[i] = true;
+						came_from[i] = Some((coord, dir));
+						frontier.push_back(next);
+					}
+				}
+			}
+		}
+		None
+	}
+
+	#[inline]
+	pub fn bfs_path(&self, from: Coord2D, to: Coord2D, team: Team) -> Option<Vec<Direction>> {
+		self.bfs_path_to(from, team, |coord| coord == to)
+	}
+
+	// cheapest route from `from` to `to`. with `charge` > 0, pushing a wall is
+	// allowed (up to `charge` times) at an extra WALL_PUSH_COST per push
+	pub fn astar_path(&self, from: Coord2D, to: Coord2D, team: Team, charge: u16) -> Option<Vec<Direction>> {
+		let bounds = self.bounds();
+		let charges = charge as usize + 1;
+		// a search state is a cell plus the charge left on arrival
+		let state_of = |coord: Coord2D, charge_left: u16| bounds.index_of(coord) * charges + charge_left as usize;
+		let heuristic = |coord: Coord2D| {
+			let dx = (coord.x as i32 - to.x as i32).abs();
+			let dy = (coord.y as i32 - to.y as i32).abs();
+			(dx + dy) as u32
+		};
+		let mut best_cost: HashMap<usize, u32> = HashMap::new();
+		let mut came_from: HashMap<usize, (usize, Direction)> = HashMap::new();
+		let mut open = BinaryHeap::new();
+		let start = state_of(from, charge);
+		best_cost.insert(start, 0);
+		open.push((Reverse(heuristic(from)), 0, from.x, from.y, charge));
+		while let Some((_, cost, x, y, charge_left)) = open.pop() {
+			let coord = Coord2D::new(x, y);
+			let state = state_of(coord, charge_left);
+			if best_cost.get(&state).map(|&c| cost > c).unwrap_or(false) {
+				continue; // stale entry
+			}
+			if coord == to {
+				let mut path = vec![];
+				let mut at = state;
+				while let Some(&(prev, dir)) = came_from.get(&at) {
+					path.push(dir);
+					at = prev;
+				}
+				path.reverse();
+				return Some(path);
+			}
+			for &dir in Direction::ALL.iter() {
+				let step = match self.free_step(coord, dir, team) {
+					Some(next) => Some((next, charge_left, 1)),
+					None if charge_left > 0
+					&& !self.coord_would_exit(coord, dir)
+					&& self.team_may_enter(team, coord.move_with(dir))
+					&& self.wall_pushable(coord.move_with(dir), dir) =>
+						Some((coord.move_with(dir), charge_left - 1, 1 + Self::WALL_PUSH_COST)),
+					None => None,
+				};
+				if let Some((next, next_charge, step_cost)) = step {
+					let next_state = state_of(next, next_charge);
+					let next_cost = cost + step_cost;
+					if best_cost.get(&next_state).map(|&c| next_cost < c).unwrap_or(true) {
+						best_cost.insert(next_state, next_cost);
+						came_from.insert(next_state, (state, dir));
+						open.push((Reverse(next_cost + heuristic(next)), next_cost, next.x, next.y, next_charge));
+					}
+				}
+			}
+		}
+		None
+	}
+
+	// astar_path for the given player, using their team and charge
+	pub fn path_for(&self, moniker: Moniker, to: Coord2D) -> Option<Vec<Direction>> {
+		let player = self.get_player(moniker)?;
+		self.astar_path(player.coord, to, player.team, player.charge)
+	}
+
+	pub fn path_to_nearest_blob(&self, moniker: Moniker) -> Option<Vec<Direction>> {
+		let player = self.get_player(moniker)?;
+		self.bfs_path_to(player.coord, player.team, |coord| self.is_blob_at(coord))
+	}
+}
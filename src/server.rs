@@ -1,19 +1,43 @@
 
 use ::common::*;
 use ::game::*;
+use ::mapgen::MapGen;
+use ::mapfile::{
+	MapError,
+	MapFile,
+};
+use ::snapshot::SnapshotLog;
+use ::chat::{
+	self,
+	CHAT_PERIOD,
+	CHAT_LOG_TARGET,
+};
+use ::clock::{
+	Clock,
+	SystemClock,
+};
+use ::transport::{
+	Transport,
+	TcpTransport,
+	MemoryTransport,
+};
+use ::metrics::{
+	Metrics,
+	MetricsEndpoint,
+	CsvDump,
+};
 
 use ::rand::{self,
 	Rng,
 };
 
-use middleman::{
-	Middleman,
-	PackedMessage,
-};
+use middleman::PackedMessage;
 
 use std::{
+	io,
 	net::SocketAddr,
 	collections::HashMap,
+	sync::Arc,
 	time::{
 		Duration,
 		Instant,
@@ -29,125 +53,420 @@ use mio::{
 
 const LISTENER_TOKEN: Token = Token(0);
 const SERVER_SLEEP_TIME: Duration = Duration::from_millis(200);
+pub const DEFAULT_BOTS: usize = 2;
+const BOT_MONIKERS: [char; 10] = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
 
 type Clients = HashMap<Token, ClientObject>;
-type Newcomers = HashMap<Token, Middleman>;
+type Newcomers = HashMap<Token, Box<dyn Transport>>; // connections that haven't said hello yet
+
+#[derive(Debug, Default)]
+pub struct ServerConfig {
+	pub fog_radius: Option<u16>,
+	pub map_gen: MapGen,
+	pub map_file: Option<MapFile>, // used instead of map_gen if present
+	pub spawn_policy: SpawnPolicy,
+	pub snapshot_period: Option<Duration>, // send clients delta snapshots this often
+	pub metrics_addr: Option<SocketAddr>, // serve Prometheus metrics here
+	pub metrics_csv: Option<(String, Duration)>, // dump metrics to this file this often
+	pub bots: usize, // computer players, besides the humans
+}
 
 #[derive(Debug)]
 struct ClientObject {
-	middleman: Middleman,
+	conn: Box<dyn Transport>,
 	moniker: Moniker,
 	last_move_at: Instant,
+	last_chat_at: Option<Instant>,
+	last_mark_at: Option<Instant>,
+	known: Option<GameState>, // under fog, the game as this client has it
+	snapshots: SnapshotLog,
 }
 
 #[derive(Clone, Debug)]
 enum ServerCtrlMsg {
 	DropNewcomerWithErr(Token, Clientward),
 	DropClientWithErr(Token, Clientward),
-	UpgradeClient(Token, Moniker),
+	UpgradeClient(Token, Moniker, Option<Team>),
 }
 
-pub fn server_enter(addr: &SocketAddr) {
-	println!("Server starting at addr {:?}!", addr);
-	let listener = MioListener::bind(addr)
-		.expect("Failed to bind");
-
-	let poll = Poll::new().unwrap();
-	let mut events = Events::with_capacity(256);
-    poll.register(&listener, LISTENER_TOKEN, Ready::readable(), PollOpt::edge()).unwrap();
-    let mut clients: Clients = HashMap::new();
-	let mut newcomers: HashMap<Token, Middleman> = HashMap::new();
-	let mut server_control: Vec<ServerCtrlMsg> = vec![];
-	let mut game_state = GameState::new_random();
-	let mut bots = init_bots(&mut game_state);
-
-	let mut outgoing_updates = vec![];
-    loop {
-    	let poll_sleep = if clients.is_empty() { None } else { Some(SERVER_SLEEP_TIME) };
-    	poll.poll(&mut events, poll_sleep).unwrap();
-    	for event in events.iter() {
-    		match event.token() {
-    			LISTENER_TOKEN => {
-    				// LISTENER ACCEPT
-    				match listener.accept() {
-						Ok((stream, _addr)) => {
-							stream.set_nodelay(true).expect("set_nodelay call failed");
-							let mm = Middleman::new(stream);
-				    		let tok = next_free_token(&clients, &newcomers);
-							println!("Newcomer client with {:?}", tok);
-				    		poll.register(&mm, tok,
-						    			Ready::readable(),
-						    			PollOpt::edge()).unwrap();
-				    		newcomers.insert(tok, mm);
-						},
-						Err(e) => {
-							println!("Listener died! {}", e);
-							panic!("Listener died");
-						},
+pub fn server_enter(addr: &SocketAddr, config: ServerConfig) {
+	info!("server starting addr={}", addr);
+	let mut server = Server::bind(addr, config).expect("Failed to bind");
+	loop {
+		server.step();
+	}
+}
+
+/*
+Everything the server keeps between passes of its loop. `server_enter` binds
+one and steps it forever, but it can be stepped by hand too, eg: from a test
+that bound it to port 0 and asked `local_addr` where it ended up. A server
+made with `new` listens nowhere: it only has the in-memory connections made
+with `connect_memory`, and its clock can be anything, so a test can play out a
+whole session without sockets or waiting.
+*/
+pub struct Server {
+	listener: Option<MioListener>,
+	clock: Arc<dyn Clock>,
+	poll: Poll,
+	events: Events,
+	clients: Clients,
+	newcomers: Newcomers,
+	server_control: Vec<ServerCtrlMsg>,
+	game_state: GameState,
+	bots: Vec<Bot>,
+	protected_since: HashMap<Moniker, (Instant, u16)>, // and the moves they had left then
+	outgoing_updates: Vec<Clientward>,
+	spawn_policy: SpawnPolicy,
+	snapshot_period: Option<Duration>,
+	last_snapshot_at: Instant,
+	tick: u64,
+	metrics: Metrics,
+	metrics_endpoint: Option<MetricsEndpoint>,
+	csv_dump: Option<CsvDump>,
+}
+impl Server {
+	pub fn bind(addr: &SocketAddr, config: ServerConfig) -> io::Result<Self> {
+		let mut server = Server::new(config, Arc::new(SystemClock))?;
+		let listener = MioListener::bind(addr)?;
+		server.poll.register(&listener, LISTENER_TOKEN, Ready::readable(), PollOpt::edge())?;
+		server.listener = Some(listener);
+		Ok(server)
+	}
+
+	pub fn new(config: ServerConfig, clock: Arc<dyn Clock>) -> io::Result<Self> {
+		let poll = Poll::new()?;
+		let metrics_endpoint = match config.metrics_addr {
+			Some(addr) => {
+				let endpoint = MetricsEndpoint::bind(&addr)?;
+				info!("serving metrics addr={}", endpoint.local_addr());
+				Some(endpoint)
+			},
+			None => None,
+		};
+		let csv_dump = match config.metrics_csv {
+			Some((ref path, period)) => Some(CsvDump::create(path, period)?),
+			None => None,
+		};
+		let invalid = |e: MapError| io::Error::new(io::ErrorKind::InvalidInput, e.to_string());
+		let mut game_state = match config.map_file {
+			Some(map) => {
+				let map = MapSource::Authored(map);
+				map.validate().map_err(invalid)?;
+				GameState::new(map, config.fog_radius)
+			},
+			None => {
+				config.map_gen.validate().map_err(invalid)?;
+				GameState::new_random(config.fog_radius, config.map_gen)
+			},
+		};
+		let bots = init_bots(&mut game_state, config.spawn_policy, config.bots, clock.now());
+		Ok(Server {
+			listener: None,
+			poll,
+			events: Events::with_capacity(256),
+			clients: HashMap::new(),
+			newcomers: HashMap::new(),
+			server_control: vec![],
+			game_state,
+			bots,
+			protected_since: HashMap::new(),
+			outgoing_updates: vec![],
+			spawn_policy: config.spawn_policy,
+			snapshot_period: config.snapshot_period,
+			last_snapshot_at: clock.now(),
+			tick: 0,
+			metrics: Metrics::new(),
+			metrics_endpoint,
+			csv_dump,
+			clock,
+		})
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		match self.listener {
+			Some(ref listener) => listener.local_addr(),
+			None => Err(io::Error::new(io::ErrorKind::NotConnected, "not listening")),
+		}
+	}
+
+	// where Prometheus can scrape, if anywhere
+	pub fn metrics_addr(&self) -> Option<SocketAddr> {
+		self.metrics_endpoint.as_ref().map(|endpoint| endpoint.local_addr())
+	}
+
+	// a new connection over in-memory pipes. the server notices it on its next step
+	pub fn connect_memory(&mut self) -> MemoryTransport {
+		let (ours, theirs) = MemoryTransport::pair(self.clock.clone());
+		let tok = next_free_token(&self.clients, &self.newcomers);
+		self.metrics.on_connection();
+		debug!("newcomer tok={} addr=memory", tok.0);
+		self.newcomers.insert(tok, Box::new(ours));
+		theirs
+	}
+
+	#[inline]
+	pub fn game_state(&self) -> &GameState {
+		&self.game_state
+	}
+
+	// one pass of the server loop. sleeps until something happens if nobody's playing
+	pub fn step(&mut self) {
+		let poll_sleep = if self.clients.is_empty() && self.csv_dump.is_none()
+		&& self.newcomers.values().all(|n| n.evented().is_some()) {
+			None
+		} else {
+			Some(SERVER_SLEEP_TIME)
+		};
+		self.step_within(poll_sleep);
+	}
+
+	// one pass of the server loop, waiting at most `timeout` for something to happen
+	pub fn step_within(&mut self, timeout: Option<Duration>) {
+		let Server {
+			ref listener, ref clock, ref poll, ref mut events, ref mut clients, ref mut newcomers,
+			ref mut server_control, ref mut game_state, ref mut bots, ref mut protected_since,
+			ref mut outgoing_updates,
+			spawn_policy, snapshot_period, ref mut last_snapshot_at, ref mut tick,
+			ref mut metrics, ref mut metrics_endpoint, ref mut csv_dump,
+		} = *self;
+		poll.poll(events, timeout).unwrap();
+		let tick_started = Instant::now(); // real time, whatever the clock says
+		let now = clock.now();
+		let mut step = Step {
+			game_state,
+			outgoing_updates,
+			server_control,
+			metrics,
+			tick: *tick,
+			now,
+		};
+		for event in events.iter() {
+			match event.token() {
+				LISTENER_TOKEN => {
+					// LISTENER ACCEPT
+					let listener = listener.as_ref().expect("event from no listener");
+					loop {
+						match listener.accept() {
+							Ok((stream, addr)) => {
+								stream.set_nodelay(true).expect("set_nodelay call failed");
+								let conn = TcpTransport::new(stream).expect("try_clone failed");
+								step.metrics.on_connection();
+								let tok = next_free_token(clients, newcomers);
+								debug!("newcomer tok={} addr={}", tok.0, addr);
+								poll.register(conn.evented().unwrap(), tok,
+											Ready::readable(),
+											PollOpt::edge()).unwrap();
+								newcomers.insert(tok, Box::new(conn));
+							},
+							Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+							Err(e) => {
+								error!("listener died err={}", e);
+								panic!("Listener died");
+							},
+						}
+					}
+				},
+				tok => {
+					if !event.readiness().is_readable() {
+						continue;
 					}
-    			},
-    			tok => {
-    				if !event.readiness().is_readable() {
-    					continue;
-    				}
-    				if clients.contains_key(&tok) {
-    					// println!("...client");
-    					handle_client_incoming(&mut clients, tok, &mut server_control,
-    						                   &mut game_state, &mut outgoing_updates);
-    				} else if newcomers.contains_key(&tok) {
-    					// println!("...newcomer");
-    					handle_newcomer_incoming(&mut newcomers, tok, &mut server_control);
-    				} else {
-    					panic!("WHOSE TOKEN??");
-    				}
-    			},
-    		}
-    	}
-
-    	if !clients.is_empty() {
-    		// freeze the game when there are no clients
-    		game_tick(&mut game_state, &mut outgoing_updates, &mut bots);
-    	}
-
-    	if !outgoing_updates.is_empty() {
-    		broadcast_outgoing_updates(&mut outgoing_updates, &mut clients, &mut server_control);
-    	}
-
-    	if !server_control.is_empty() {
-    		do_server_control(&mut server_control, &mut newcomers, &mut clients, &poll,
-    			              &mut game_state, &mut outgoing_updates);
-    	}
-    }
+					if clients.contains_key(&tok) {
+						handle_client_incoming(clients, tok, &mut step);
+					} else if newcomers.contains_key(&tok) {
+						handle_newcomer_incoming(newcomers, tok, step.server_control, step.metrics);
+					} else {
+						panic!("WHOSE TOKEN??");
+					}
+				},
+			}
+		}
+		// connections that poll can't see just get checked every time
+		let unevented_clients: Vec<Token> = clients.iter()
+			.filter(|&(_, c)| c.conn.evented().is_none())
+			.map(|(&tok, _)| tok)
+			.collect();
+		for tok in unevented_clients {
+			handle_client_incoming(clients, tok, &mut step);
+		}
+		let unevented_newcomers: Vec<Token> = newcomers.iter()
+			.filter(|&(_, n)| n.evented().is_none())
+			.map(|(&tok, _)| tok)
+			.collect();
+		for tok in unevented_newcomers {
+			handle_newcomer_incoming(newcomers, tok, step.server_control, step.metrics);
+		}
+
+		if !clients.is_empty() {
+			// freeze the game when there are no clients
+			game_tick(step.game_state, step.outgoing_updates, bots, now);
+			expire_protection(step.game_state, protected_since, step.outgoing_updates, now);
+			*tick += 1;
+		}
+
+		if !step.outgoing_updates.is_empty() {
+			broadcast_outgoing_updates(step.outgoing_updates, clients, step.server_control,
+				                       step.game_state, step.metrics);
+		}
+
+		if let Some(period) = snapshot_period {
+			if now.duration_since(*last_snapshot_at) >= period {
+				send_snapshots(clients, step.server_control, step.game_state, step.metrics);
+				*last_snapshot_at = now;
+			}
+		}
+
+		if !step.server_control.is_empty() {
+			do_server_control(newcomers, clients, poll, spawn_policy, &mut step);
+		}
+
+		if !clients.is_empty() {
+			step.metrics.on_tick(tick_started.elapsed());
+		}
+		if let Some(ref mut endpoint) = *metrics_endpoint {
+			step.metrics.set_population(clients.len(), newcomers.len());
+			endpoint.maybe_publish(step.metrics, now);
+		}
+		if let Some(ref mut csv_dump) = *csv_dump {
+			step.metrics.set_population(clients.len(), newcomers.len());
+			csv_dump.maybe_dump(step.metrics);
+		}
+	}
+}
+
+/*
+What one pass of the server loop hands on to the handlers it calls: the game,
+what to broadcast and do once they're done, the metrics, and the time.
+*/
+struct Step<'a> {
+	game_state: &'a mut GameState,
+	outgoing_updates: &'a mut Vec<Clientward>,
+	server_control: &'a mut Vec<ServerCtrlMsg>,
+	metrics: &'a mut Metrics,
+	tick: u64,
+	now: Instant,
+}
+
+// sends `msg`, counting it
+fn send_metered(conn: &mut dyn Transport, metrics: &mut Metrics, msg: &Clientward) -> io::Result<()> {
+	let packed = PackedMessage::new(msg).expect("failed to pack");
+	conn.send(& packed)?;
+	metrics.on_sent(msg, packed.byte_len());
+	Ok(())
 }
 
 #[inline]
 fn broadcast_outgoing_updates(outgoing_updates: &mut Vec<Clientward>, clients: &mut Clients,
-	                          server_control:  &mut Vec<ServerCtrlMsg>)
+	                          server_control:  &mut Vec<ServerCtrlMsg>, game_state: &GameState,
+	                          metrics: &mut Metrics)
 {
 	use self::ServerCtrlMsg::*;
 	for msg in outgoing_updates.drain(..) {
-		// println!("broadcasting {:?}", &msg);
 		let packed = PackedMessage::new(& msg).expect("failed to pack");
-		match msg {
-			Clientward::AddPlayer(moniker, _coord) => {
-				for (&tok, client_object) in clients.iter_mut() {
-					if client_object.moniker == moniker {
-						continue; // no need to add yourself.
-					}
-					if client_object.middleman.send_packed(& packed).is_err() {
-						server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
-					}
-				}
-			},
-			_ => {
-				for (&tok, client_object) in clients.iter_mut() {
-					if client_object.middleman.send_packed(& packed).is_err() {
-						server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
-					}
-				}
-			},
-		} 
-		
+		for (&tok, client_object) in clients.iter_mut() {
+			if !passes_on(client_object, &msg) {
+				continue;
+			}
+			if client_object.conn.send(& packed).is_err() {
+				server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
+			} else {
+				metrics.on_sent(&msg, packed.byte_len());
+			}
+		}
+	}
+	if game_state.fog_radius().is_some() {
+		sync_fogged_views(clients, server_control, game_state, metrics);
+	}
+}
+
+// whether a client gets `msg`. under fog it only hears of the players it
+// knows about, and replays what it hears on its known copy like the client will
+fn passes_on(client_object: &mut ClientObject, msg: &Clientward) -> bool {
+	let moniker = client_object.moniker;
+	let known = match client_object.known {
+		Some(ref mut known) => known,
+		None => return match *msg {
+			Clientward::AddPlayer(m, _) => m != moniker, // no need to add yourself.
+			_ => true,
+		},
+	};
+	match *msg {
+		// whoever is in sight gets added by sync_fogged_views, as they are by then
+		Clientward::AddPlayer(..) => false,
+		Clientward::RemovePlayer(m) => known.try_remove_player(m),
+		Clientward::UpdMove(m, dir) => {
+			if !known.contains_player(m) {
+				return false;
+			}
+			known.move_moniker_in_dir(m, dir);
+			true
+		},
+		Clientward::EndProtection(m) => known.end_protection(m),
+		_ => true,
+	}
+}
+
+/*
+After the step's updates, each fogged client hears of players coming into or
+going out of sight. If its copy of the game still doesn't show it what the
+server's does, eg: a wall was pushed out of its sight or a blob respawned, it
+gets its whole view again.
+*/
+fn sync_fogged_views(clients: &mut Clients, server_control: &mut Vec<ServerCtrlMsg>,
+	                 game_state: &GameState, metrics: &mut Metrics)
+{
+	use self::ServerCtrlMsg::*;
+	for (&tok, client_object) in clients.iter_mut() {
+		let moniker = client_object.moniker;
+		let known = match client_object.known {
+			Some(ref mut known) => known,
+			None => continue,
+		};
+		let visible = game_state.players_visible_to(moniker);
+		let mut msgs = vec![];
+		let gone: Vec<Moniker> = known.player_iter()
+			.map(|(&m, _)| m)
+			.filter(|m| !visible.contains_key(m))
+			.collect();
+		for m in gone {
+			known.try_remove_player(m);
+			msgs.push(Clientward::RemovePlayer(m));
+		}
+		for (m, player) in visible {
+			if !known.contains_player(m) && known.try_insert_player(m, player.clone()) {
+				msgs.push(Clientward::AddPlayer(m, player));
+			}
+		}
+		if !game_state.same_view(known, moniker) {
+			let view = game_state.essence_visible_to(moniker);
+			*known = GameState::from_essence(view.clone()).expect("own map");
+			msgs = vec![Clientward::Refresh(view)];
+		}
+		for msg in msgs.iter() {
+			if send_metered(&mut *client_object.conn, metrics, msg).is_err() {
+				server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
+				break;
+			}
+		}
+	}
+}
+
+// lets lagging clients catch up without a full resync. each gets what changed
+// since the last snapshot it acknowledged
+fn send_snapshots(clients: &mut Clients, server_control: &mut Vec<ServerCtrlMsg>,
+	              game_state: &GameState, metrics: &mut Metrics)
+{
+	use self::ServerCtrlMsg::*;
+	for (&tok, client_object) in clients.iter_mut() {
+		let view = game_state.essence_visible_to(client_object.moniker);
+		if let Some((id, base, delta)) = client_object.snapshots.next_snapshot(view.clone()) {
+			if let Some(ref mut known) = client_object.known {
+				*known = GameState::from_essence(view).expect("own map"); // the client starts over from it
+			}
+			if send_metered(&mut *client_object.conn, metrics, & Clientward::Snapshot(id, base, delta)).is_err() {
+				server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
+			}
+		}
 	}
 }
 
@@ -156,14 +475,14 @@ struct Bot {
 	last_move_at: Instant,
 }
 
-fn init_bots(game_state: &mut GameState) -> Vec<Bot> {
+fn init_bots(game_state: &mut GameState, spawn_policy: SpawnPolicy, count: usize, now: Instant) -> Vec<Bot> {
 	let mut bots = vec![];
-	for bot_moniker in ['0', '1']
-	.iter().map(|&c| Moniker(c))
+	for bot_moniker in BOT_MONIKERS.iter().take(count).map(|&c| Moniker(c))
 	{
-		let coord = game_state.random_free_spot().expect("No coord to put bot");
-		if game_state.try_add_player(bot_moniker, coord) {
-			bots.push(Bot { moniker: bot_moniker, last_move_at: Instant::now() });
+		let team = game_state.smallest_team();
+		let coord = game_state.spawn_spot_for(team, spawn_policy).expect("No coord to put bot");
+		if game_state.try_add_player(bot_moniker, coord, team) {
+			bots.push(Bot { moniker: bot_moniker, last_move_at: now });
 		} else {
 			panic!("Failed to place bot {:?}", bot_moniker);
 		}
@@ -172,64 +491,130 @@ fn init_bots(game_state: &mut GameState) -> Vec<Bot> {
 }
 
 fn game_tick(game_state: &mut GameState, outgoing_updates: &mut Vec<Clientward>,
-	         bots: &mut Vec<Bot>)
+	         bots: &mut [Bot], now: Instant)
 {
 	let mut rng = rand::thread_rng();
 	for bot in bots.iter_mut() {
-		if bot.last_move_at.elapsed() > BOT_MOVE_PERIOD {
+		if now.duration_since(bot.last_move_at) > BOT_MOVE_PERIOD {
 			let moniker = bot.moniker;
-			let dir = *rng.choose(&DIR_CHOICES).unwrap();
+			// head for the nearest blob, now and then wandering off
+			let dir = match game_state.path_to_nearest_blob(moniker) {
+				Some(ref path) if !path.is_empty() && !rng.gen_weighted_bool(BOT_WANDER_ONE_IN) => path[0],
+				_ => *rng.choose(&DIR_CHOICES).unwrap(),
+			};
 			if game_state.move_moniker_in_dir(moniker, dir) {
-				bot.last_move_at = Instant::now();
+				bot.last_move_at = now;
 				outgoing_updates.push(Clientward::UpdMove(moniker, dir))
 			}
 		}
 	}
 }
 
+/*
+Spawn protection runs out after SPAWN_PROTECTION_TIME even for players who
+stand still. The game only counts moves, so the server keeps the time and
+announces when it's up, for clients to replay. A player who has more moves of
+protection left than last time has just been given it again.
+*/
+fn expire_protection(game_state: &mut GameState, protected_since: &mut HashMap<Moniker, (Instant, u16)>,
+	                 outgoing_updates: &mut Vec<Clientward>, now: Instant)
+{
+	let mut still_protected = HashMap::new();
+	let mut expired = vec![];
+	for (&moniker, player) in game_state.player_iter().filter(|&(_, p)| p.is_protected()) {
+		let since = match protected_since.get(&moniker) {
+			Some(&(since, moves_left)) if player.protected_moves <= moves_left => since,
+			_ => now,
+		};
+		if now.duration_since(since) >= SPAWN_PROTECTION_TIME {
+			expired.push(moniker);
+		} else {
+			still_protected.insert(moniker, (since, player.protected_moves));
+		}
+	}
+	*protected_since = still_protected;
+	for moniker in expired {
+		if game_state.end_protection(moniker) {
+			outgoing_updates.push(Clientward::EndProtection(moniker));
+		}
+	}
+}
+
 #[inline]
-fn do_server_control(server_control: &mut Vec<ServerCtrlMsg>, newcomers: &mut Newcomers,
-	                 clients: &mut Clients, poll: &Poll, game_state: &mut GameState,
-	                 outgoing_updates: &mut Vec<Clientward>)
+fn do_server_control(newcomers: &mut Newcomers, clients: &mut Clients, poll: &Poll,
+	                 spawn_policy: SpawnPolicy, step: &mut Step)
 {
+	let Step { ref mut server_control, ref mut game_state, ref mut outgoing_updates, ref mut metrics, now, .. } = *step;
 	for ctrl_msg in server_control.drain(..) {
-		println!("handing control msg {:?}", &ctrl_msg);
 		match ctrl_msg {
 			ServerCtrlMsg::DropNewcomerWithErr(tok, msg) => {
-				if let Some(mut mm) = newcomers.remove(&tok) {
-					let _ = poll.deregister(&mm);
-					let _ = mm.send(& msg);
+				if let Some(mut conn) = newcomers.remove(&tok) {
+					info!("dropping newcomer tok={} reason={}", tok.0, msg.kind());
+					metrics.on_handshake(msg.kind());
+					if let Some(evented) = conn.evented() {
+						let _ = poll.deregister(evented);
+					}
+					let _ = send_metered(&mut *conn, metrics, & msg);
 				}
 			},
 			ServerCtrlMsg::DropClientWithErr(tok, msg) => {
 				if let Some(mut obj) = clients.remove(&tok) {
-					let _ = poll.deregister(& obj.middleman);
-					let _ = obj.middleman.send(& msg);
+					info!("dropping client tok={} moniker={} reason={}", tok.0, obj.moniker.0, msg.kind());
+					metrics.forget_client(obj.moniker);
+					if let Some(evented) = obj.conn.evented() {
+						let _ = poll.deregister(evented);
+					}
+					let _ = send_metered(&mut *obj.conn, metrics, & msg);
 					if game_state.try_remove_player(obj.moniker) {
 						outgoing_updates.push(Clientward::RemovePlayer(obj.moniker));
 					}
 				}
 			},
-			ServerCtrlMsg::UpgradeClient(tok, moniker) => {
-				let mut mm = newcomers.remove(&tok).expect("remove fail");
+			ServerCtrlMsg::UpgradeClient(tok, moniker, requested_team) => {
+				let mut conn = newcomers.remove(&tok).expect("remove fail");
 				if game_state.contains_player(moniker) {
-					let _ = mm.send(& Clientward::ErrorTakenMoniker);
+					info!("refused newcomer tok={} moniker={} reason=ErrorTakenMoniker", tok.0, moniker.0);
+					metrics.on_handshake("ErrorTakenMoniker");
+					let _ = send_metered(&mut *conn, metrics, & Clientward::ErrorTakenMoniker);
 				} else {
-					let coord = game_state.random_free_spot().expect("GAME TOO FULL");
-					if game_state.try_add_player(moniker, coord) {
-						if mm.send(& Clientward::Welcome(game_state.get_essence().clone())).is_ok() {
-							poll.deregister(&mm).expect("deregister fail");
-							poll.reregister(&mm, tok,
+					let team = requested_team.unwrap_or_else(|| game_state.smallest_team());
+					let coord = match game_state.spawn_spot_for(team, spawn_policy) {
+						Some(coord) if game_state.try_add_player(moniker, coord, team) => coord,
+						_ => {
+							info!("refused newcomer tok={} moniker={} team={:?} reason=ErrorGameFull", tok.0, moniker.0, team);
+							metrics.on_handshake("ErrorGameFull");
+							let _ = send_metered(&mut *conn, metrics, & Clientward::ErrorGameFull);
+							continue;
+						},
+					};
+					let view = game_state.essence_visible_to(moniker);
+					if send_metered(&mut *conn, metrics, & Clientward::Welcome(view.clone())).is_ok() {
+						metrics.on_handshake("welcomed");
+						if let Some(evented) = conn.evented() {
+							poll.reregister(evented, tok,
 						    			Ready::readable() | Ready::writable(),
 						    			PollOpt::edge()).expect("reregister fail");
-				    		let x = ClientObject {
-				    			moniker: moniker,
-				    			middleman: mm,
-				    			last_move_at: Instant::now(),
-				    		};
-				    		clients.insert(tok, x);
-				    		outgoing_updates.push(Clientward::AddPlayer(moniker, coord));
 						}
+			    		let x = ClientObject {
+			    			moniker,
+			    			conn,
+			    			last_move_at: now,
+			    			last_chat_at: None,
+			    			last_mark_at: None,
+			    			known: if game_state.fog_radius().is_some() {
+			    				Some(GameState::from_essence(view.clone()).expect("own map"))
+			    			} else {
+			    				None
+			    			},
+			    			snapshots: SnapshotLog::new(view),
+			    		};
+			    		clients.insert(tok, x);
+			    		info!("joined tok={} moniker={} team={:?} at={:?}", tok.0, moniker.0, team, coord);
+			    		let player = game_state.get_player(moniker).unwrap().clone();
+			    		outgoing_updates.push(Clientward::AddPlayer(moniker, player));
+					} else {
+						metrics.on_handshake("ErrorSocketDead");
+						game_state.try_remove_player(moniker);
 					}
 				}
 			},
@@ -238,28 +623,91 @@ fn do_server_control(server_control: &mut Vec<ServerCtrlMsg>, newcomers: &mut Ne
 }
 
 #[inline]
-fn handle_client_incoming(clients: &mut Clients, tok: Token, server_control: &mut Vec<ServerCtrlMsg>,
-	                      game_state: &mut GameState, outgoing_updates: &mut Vec<Clientward>)
-{
+fn handle_client_incoming(clients: &mut Clients, tok: Token, step: &mut Step) {
 	use self::ServerCtrlMsg::*;
+	let Step { ref mut server_control, ref mut game_state, ref mut outgoing_updates, ref mut metrics, tick, now } = *step;
 	let client_object = clients.get_mut(&tok).expect("clients incoming");
 	let moniker = client_object.moniker;
 	loop {
-		let x = client_object.middleman.recv::<Serverward>();
-		println!("got from client {:?} {:?}", tok, &x);
+		let x = match client_object.conn.recv() {
+			Ok(Some(packed)) => match packed.unpack::<Serverward>() {
+				Ok(msg) => {
+					trace!("recv tok={} moniker={} kind={}", tok.0, moniker.0, msg.kind());
+					metrics.on_received(&msg, packed.byte_len());
+					Ok(Some(msg))
+				},
+				Err(_) => {
+					server_control.push(DropClientWithErr(tok, Clientward::ErrorExpectedReq));
+					break;
+				},
+			},
+			Ok(None) => Ok(None),
+			Err(e) => Err(e),
+		};
 		match x {
 			Ok(Some(Serverward::ReqMove(dir))) => {
-				if client_object.last_move_at.elapsed() < MOVE_PERIOD {
-					println!("tok {:?} is moving too fast", tok);
+				let move_period = game_state.get_player(moniker)
+					.map(move_period_of)
+					.unwrap_or(MOVE_PERIOD);
+				if now.duration_since(client_object.last_move_at) < move_period {
+					debug!("too fast tok={} moniker={}", tok.0, moniker.0);
+					metrics.on_dropped_move("too_fast");
 					continue; //moving too fast
 				} 
 				if game_state.move_moniker_in_dir(moniker, dir) {
-					client_object.last_move_at = Instant::now();
+					client_object.last_move_at = now;
 					outgoing_updates.push(Clientward::UpdMove(moniker, dir))
+				} else {
+					metrics.on_dropped_move("illegal");
 				}
 				// don't drop clients if they misbehave. just silently drop msg
 			},
-			Ok(None) => break, // spurious wakeup
+			Ok(Some(Serverward::AckSnapshot(id))) => client_object.snapshots.ack(id),
+			Ok(Some(Serverward::Chat(text))) => {
+				if let Some(at) = client_object.last_chat_at {
+					if now.duration_since(at) < CHAT_PERIOD {
+						debug!("chat too fast tok={} moniker={}", tok.0, moniker.0);
+						metrics.on_dropped_chat("too_fast");
+						continue;
+					}
+				}
+				match chat::clean(&text) {
+					Some(text) => {
+						info!(target: CHAT_LOG_TARGET, "chat moniker={} text={:?}", moniker.0, text);
+						client_object.last_chat_at = Some(now);
+						outgoing_updates.push(Clientward::Chat { from: moniker, text });
+					},
+					None => metrics.on_dropped_chat("empty"),
+				}
+			},
+			Ok(Some(Serverward::Mark(at))) => {
+				if !game_state.bounds().contains(at) {
+					metrics.on_dropped_mark("off_map");
+					continue;
+				}
+				if let Some(last) = client_object.last_mark_at {
+					if now.duration_since(last) < MARK_PERIOD {
+						debug!("mark too fast tok={} moniker={}", tok.0, moniker.0);
+						metrics.on_dropped_mark("too_fast");
+						continue;
+					}
+				}
+				debug!("mark moniker={} at={:?}", moniker.0, at);
+				client_object.last_mark_at = Some(now);
+				outgoing_updates.push(Clientward::Mark { from: moniker, at });
+			},
+			Ok(Some(Serverward::Ping(id, rtt_micros))) => {
+				if let Some(micros) = rtt_micros {
+					let rtt = Duration::from_micros(micros as u64);
+					debug!("rtt tok={} moniker={} rtt_ms={:.1}", tok.0, moniker.0, secs_f32(rtt) * 1000.0);
+					metrics.on_client_rtt(moniker, rtt);
+				}
+				if send_metered(&mut *client_object.conn, metrics, & Clientward::Pong(id, tick)).is_err() {
+					server_control.push(DropClientWithErr(tok, Clientward::ErrorSocketDead));
+					break;
+				}
+			},
+			Ok(None) => break,
 			Ok(Some(_msg)) => {
 				server_control.push(DropClientWithErr(tok, Clientward::ErrorExpectedReq));
 				break;
@@ -273,21 +721,32 @@ fn handle_client_incoming(clients: &mut Clients, tok: Token, server_control: &mu
 }
 
 #[inline]
-fn handle_newcomer_incoming(newcomers: &mut Newcomers, tok: Token, server_control: &mut Vec<ServerCtrlMsg>) {
+fn handle_newcomer_incoming(newcomers: &mut Newcomers, tok: Token, server_control: &mut Vec<ServerCtrlMsg>,
+	                        metrics: &mut Metrics) {
 	use self::ServerCtrlMsg::*;
-	let mut done = false; // drop all but first message
-	let mm: &mut Middleman = newcomers.get_mut(&tok).expect("newcomer incoming");
-	if mm.recv_all_map( |_me, msg| {
-		println!("got from newcomer {:?} {:?}", tok, &msg);
-		if done { return }
-		if let Serverward::Hello(moniker) = msg {
-			server_control.push(UpgradeClient(tok, moniker));
-		} else {
+	let newcomer = newcomers.get_mut(&tok).expect("newcomer incoming");
+	// only the first message matters. whatever follows is dropped with the newcomer
+	let packed = match newcomer.recv() {
+		Ok(Some(packed)) => packed,
+		Ok(None) => return,
+		Err(_) => {
+			server_control.push(DropNewcomerWithErr(tok, Clientward::ErrorSocketDead));
+			return;
+		},
+	};
+	let msg = match packed.unpack::<Serverward>() {
+		Ok(msg) => msg,
+		Err(_) => {
 			server_control.push(DropNewcomerWithErr(tok, Clientward::ErrorExpectedHello));
-		}
-		done = true;
-	}).1.is_err() {
-		server_control.push(DropNewcomerWithErr(tok, Clientward::ErrorSocketDead));
+			return;
+		},
+	};
+	trace!("recv tok={} kind={}", tok.0, msg.kind());
+	metrics.on_received(&msg, packed.byte_len());
+	if let Serverward::Hello(moniker, team) = msg {
+		server_control.push(UpgradeClient(tok, moniker, team));
+	} else {
+		server_control.push(DropNewcomerWithErr(tok, Clientward::ErrorExpectedHello));
 	}
 }
 
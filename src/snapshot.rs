@@ -0,0 +1,114 @@
+use ::game::*;
+use ::grid::WallFlips;
+
+use std::collections::VecDeque;
+
+/////////////////////////////////////////////////////////////
+
+pub type SnapshotId = u32;
+
+// the Welcome essence. both sides start from it, so the first delta has a base
+pub const WELCOME_SNAPSHOT: SnapshotId = 0;
+
+// unacknowledged snapshots the server remembers per client. any older are forgotten,
+// and their acks ignored. deltas keep going against the last ack either way
+const MAX_PENDING: usize = 8;
+
+/*
+What it takes to turn one essence into another. Only holds what changed:
+players that moved or changed, walls that flipped since, blobs that came and went.
+The map and fog radius never change over a game, so they aren't in here.
+*/
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EssenceDelta {
+	pub players: Vec<(Moniker, PlayerObject)>, // new or changed
+	pub removed_players: Vec<Moniker>,
+	pub wall_flips: WallFlips, // toggle these
+	pub power_blobs: Vec<(Coord2D, PickupKind)>, // new or changed
+	pub removed_blobs: Vec<Coord2D>,
+	pub team_scores: Option<[u32; 2]>,
+	pub sync_rng: Option<LCGenerator>,
+}
+impl EssenceDelta {
+	pub fn is_empty(&self) -> bool {
+		self.players.is_empty()
+		&& self.removed_players.is_empty()
+		&& self.wall_flips.is_empty()
+		&& self.power_blobs.is_empty()
+		&& self.removed_blobs.is_empty()
+		&& self.team_scores.is_none()
+		&& self.sync_rng.is_none()
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+// server side. the snapshots sent to one client, and the last one it acknowledged
+#[derive(Debug)]
+pub struct SnapshotLog {
+	next_id: SnapshotId,
+	acked: (SnapshotId, GameStateEssence),
+	pending: VecDeque<(SnapshotId, GameStateEssence)>,
+}
+impl SnapshotLog {
+	pub fn new(welcome: GameStateEssence) -> Self {
+		SnapshotLog {
+			next_id: WELCOME_SNAPSHOT + 1,
+			acked: (WELCOME_SNAPSHOT, welcome),
+			pending: VecDeque::new(),
+		}
+	}
+
+	// the next snapshot to send for `view`, as (id, base, delta).
+	// None if the client already has it, or has been sent it
+	pub fn next_snapshot(&mut self, view: GameStateEssence) -> Option<(SnapshotId, SnapshotId, EssenceDelta)> {
+		let latest = self.pending.back().map(|(_, e)| e).unwrap_or(&self.acked.1);
+		if *latest == view {
+			return None;
+		}
+		let id = self.next_id;
+		self.next_id = self.next_id.wrapping_add(1);
+		let delta = view.delta_from(&self.acked.1);
+		if self.pending.len() >= MAX_PENDING {
+			self.pending.pop_front();
+		}
+		self.pending.push_back((id, view));
+		Some((id, self.acked.0, delta))
+	}
+
+	// later deltas are against this one. acks for forgotten or older snapshots do nothing
+	pub fn ack(&mut self, id: SnapshotId) {
+		if let Some(at) = self.pending.iter().position(|&(x, _)| x == id) {
+			let acked = self.pending.drain(..at+1).next_back().unwrap();
+			self.acked = acked;
+		}
+	}
+}
+
+// client side. the snapshots received that later deltas might still be against
+#[derive(Debug)]
+pub struct SnapshotBuffer {
+	kept: VecDeque<(SnapshotId, GameStateEssence)>,
+}
+impl SnapshotBuffer {
+	pub fn new(welcome: GameStateEssence) -> Self {
+		let mut kept = VecDeque::new();
+		kept.push_back((WELCOME_SNAPSHOT, welcome));
+		SnapshotBuffer { kept }
+	}
+
+	// rebuilds snapshot `id` from the kept `base`. None if `base` is gone.
+	// the server never goes back to an older base, so anything before it is dropped
+	pub fn resolve(&mut self, id: SnapshotId, base: SnapshotId,
+		           delta: &EssenceDelta) -> Option<GameStateEssence> {
+		let at = self.kept.iter().position(|&(x, _)| x == base)?;
+		self.kept.drain(..at);
+		let mut essence = self.kept[0].1.clone();
+		essence.apply_delta(delta);
+		if self.kept.len() > MAX_PENDING {
+			self.kept.remove(1);
+		}
+		self.kept.push_back((id, essence.clone()));
+		Some(essence)
+	}
+}
@@ -0,0 +1,192 @@
+use ::common::*;
+use ::clock::Clock;
+
+use middleman::{
+	Message,
+	Middleman,
+	PackedMessage,
+};
+
+use mio::Evented;
+
+use std::{
+	collections::VecDeque,
+	fmt,
+	io,
+	sync::{
+		Arc,
+		Mutex,
+	},
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+/*
+One end of a connection, as the server sees it. Messages go in and come out
+whole and in order. `recv` gives Ok(None) when nothing has arrived yet, and an
+error once the other end is gone.
+*/
+pub trait Transport: Send + fmt::Debug {
+	fn send(&mut self, packed: &PackedMessage) -> io::Result<()>;
+
+	fn recv(&mut self) -> io::Result<Option<PackedMessage>>;
+
+	// something to register with a mio::Poll, if messages arriving wake one up.
+	// transports without one are checked on every step instead
+	fn evented(&self) -> Option<&dyn Evented> {
+		None
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+// a real socket
+#[derive(Debug)]
+pub struct TcpTransport {
+	middleman: Middleman,
+	probe: MioStream, // the same socket, for noticing when it's closed
+}
+impl TcpTransport {
+	pub fn new(stream: MioStream) -> io::Result<Self> {
+		let probe = stream.try_clone()?;
+		Ok(TcpTransport {
+			middleman: Middleman::new(stream),
+			probe,
+		})
+	}
+
+	// true once the other end has closed the connection
+	fn hung_up(&self) -> bool {
+		match self.probe.peek(&mut [0u8; 1]) {
+			Ok(0) => true,
+			Ok(_) => false, // more on the way
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
+			Err(_) => true,
+		}
+	}
+}
+impl Transport for TcpTransport {
+	fn send(&mut self, packed: &PackedMessage) -> io::Result<()> {
+		self.middleman.send_packed(packed)
+	}
+
+	fn recv(&mut self) -> io::Result<Option<PackedMessage>> {
+		match self.middleman.recv_packed() {
+			Ok(Some(packed)) => Ok(Some(packed)),
+			// middleman reads a closed socket as no news
+			Ok(None) if self.hung_up() => Err(io::ErrorKind::ConnectionAborted.into()),
+			Ok(None) => Ok(None),
+			Err(e) => Err(io::Error::other(format!("{:?}", e))),
+		}
+	}
+
+	fn evented(&self) -> Option<&dyn Evented> {
+		Some(&self.middleman)
+	}
+}
+
+/////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+struct Link {
+	lanes: [VecDeque<(Instant, Vec<u8>)>; 2], // each end's inbox of packed bytes, with when each lands
+	latency: Duration,
+	held: bool,
+	closed: bool,
+}
+
+/*
+One end of a pair of in-memory pipes, for simulations. A message sent now
+lands `latency` later by the clock both ends share. While the link is held,
+nothing lands at all. Either end can change how the link behaves, and dropping
+either end closes it, like hanging up a socket.
+*/
+#[derive(Debug)]
+pub struct MemoryTransport {
+	link: Arc<Mutex<Link>>,
+	side: usize,
+	clock: Arc<dyn Clock>,
+}
+impl MemoryTransport {
+	pub fn pair(clock: Arc<dyn Clock>) -> (MemoryTransport, MemoryTransport) {
+		let link = Arc::new(Mutex::new(Link {
+			lanes: [VecDeque::new(), VecDeque::new()],
+			latency: Duration::from_millis(0),
+			held: false,
+			closed: false,
+		}));
+		let a = MemoryTransport { link: link.clone(), side: 0, clock: clock.clone() };
+		let b = MemoryTransport { link, side: 1, clock };
+		(a, b)
+	}
+
+	// for messages sent from now on, both ways
+	pub fn set_latency(&self, latency: Duration) {
+		self.link.lock().unwrap().latency = latency;
+	}
+
+	// while held, messages pile up instead of landing
+	pub fn set_held(&self, held: bool) {
+		self.link.lock().unwrap().held = held;
+	}
+
+	pub fn close(&self) {
+		self.link.lock().unwrap().closed = true;
+	}
+
+	// messages sent to either end that haven't been received yet
+	pub fn in_flight(&self) -> usize {
+		let link = self.link.lock().unwrap();
+		link.lanes[0].len() + link.lanes[1].len()
+	}
+
+	pub fn send_msg<M: Message>(&mut self, msg: &M) -> io::Result<()> {
+		let packed = PackedMessage::new(msg)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+		self.send(&packed)
+	}
+
+	pub fn recv_msg<M: Message>(&mut self) -> io::Result<Option<M>> {
+		match self.recv()? {
+			Some(packed) => packed.unpack()
+				.map(Some)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))),
+			None => Ok(None),
+		}
+	}
+}
+impl Transport for MemoryTransport {
+	fn send(&mut self, packed: &PackedMessage) -> io::Result<()> {
+		let mut link = self.link.lock().unwrap();
+		if link.closed {
+			return Err(io::ErrorKind::BrokenPipe.into());
+		}
+		let lands_at = self.clock.now() + link.latency;
+		link.lanes[1 - self.side].push_back((lands_at, packed.get_raw().clone()));
+		Ok(())
+	}
+
+	fn recv(&mut self) -> io::Result<Option<PackedMessage>> {
+		let mut link = self.link.lock().unwrap();
+		let now = self.clock.now();
+		let landed = !link.held && link.lanes[self.side].front()
+			.map(|&(lands_at, _)| lands_at <= now)
+			.unwrap_or(false);
+		if landed {
+			return Ok(link.lanes[self.side].pop_front().map(|(_, raw)| PackedMessage::from_raw(raw)));
+		}
+		if link.closed && link.lanes[self.side].is_empty() {
+			return Err(io::ErrorKind::ConnectionAborted.into());
+		}
+		Ok(None)
+	}
+}
+impl Drop for MemoryTransport {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
@@ -0,0 +1,360 @@
+use ::common::*;
+use ::game::*;
+use ::snapshot::SnapshotBuffer;
+use ::chat::{
+	self,
+	ChatLog,
+	CHAT_MAX_CHARS,
+};
+
+use std::{
+	io::{
+		self,
+		Write,
+	},
+	net::SocketAddr,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+use middleman::Middleman;
+
+use mio::{
+	Poll,
+	Ready,
+	PollOpt,
+	Events,
+	Token,
+};
+
+use termion::{
+	self,
+	AsyncReader,
+	clear,
+	color,
+	cursor,
+	event::Key,
+	input::{
+		Keys,
+		TermRead,
+	},
+	raw::{
+		IntoRawMode,
+		RawTerminal,
+	},
+	style,
+};
+
+/*
+The game in a terminal, for when there's no window to open. Each cell is two
+characters wide, so the board comes out roughly square. Everything else works
+as in the windowed client.
+*/
+
+const TUI_TOKEN: Token = Token(0);
+const POLL_TIMEOUT: Duration = Duration::from_millis(20);
+
+// indexed by charge, like CHARGE_COLORS in the windowed client
+const CHARGE_FG: [color::AnsiValue; 4] = [
+	color::AnsiValue(15), // white
+	color::AnsiValue(217), // pink
+	color::AnsiValue(120), // green
+	color::AnsiValue(201), // magenta
+];
+const TEAM_BG: [color::AnsiValue; 2] = [color::AnsiValue(160), color::AnsiValue(27)];
+const BASE_BG: [color::AnsiValue; 2] = [color::AnsiValue(52), color::AnsiValue(17)];
+// indexed by PickupKind::index
+const PICKUP_FG: [color::AnsiValue; 5] = [
+	color::AnsiValue(226), // charge
+	color::AnsiValue(51),  // speed
+	color::AnsiValue(147), // ghost
+	color::AnsiValue(208), // teleport
+	color::AnsiValue(130), // wall spawner
+];
+const WALL_FG: color::AnsiValue = color::AnsiValue(88);
+const PORTAL_FG: color::AnsiValue = color::AnsiValue(129);
+const CONVEYOR_FG: color::AnsiValue = color::AnsiValue(248);
+const ICE_FG: color::AnsiValue = color::AnsiValue(153);
+const PIT_FG: color::AnsiValue = color::AnsiValue(240);
+const FOG_FG: color::AnsiValue = color::AnsiValue(236);
+const MARK_BG: color::AnsiValue = color::AnsiValue(220); // map pings, drawn over everything
+
+// monikers come off the wire. anything but a plain one-column character could
+// move the cursor, clear the screen or push the rest of the row out of line
+fn drawable(moniker: Moniker) -> char {
+	if moniker.0.is_ascii_graphic() { moniker.0 } else { '?' }
+}
+
+pub fn tui_enter(addr: &SocketAddr, my_moniker: Moniker, my_team: Option<Team>) {
+	info!("terminal client starting addr={} moniker={}", addr, my_moniker.0);
+	match StdStream::connect(addr) {
+		Ok(stream) => {
+			stream.set_nodelay(true).expect("set_nodelay call failed");
+			let mm = Middleman::new(MioStream::from_stream(stream).unwrap());
+			tui_go(mm, my_moniker, my_team);
+		},
+		Err(e) => {
+			error!("connect failed addr={} err={}", addr, e);
+		}
+	}
+}
+
+fn tui_go(mut mm: Middleman, my_moniker: Moniker, my_team: Option<Team>) {
+	let poll = Poll::new().unwrap();
+	let mut events = Events::with_capacity(128);
+	poll.register(&mm, TUI_TOKEN,
+				Ready::readable(),
+				PollOpt::edge()).unwrap();
+	let (snapshots, game_state) = match client_handshake(&mut mm, &poll, &mut events, my_moniker, my_team) {
+		Some(welcomed) => welcomed,
+		None => return,
+	};
+
+	let stdout = io::stdout().into_raw_mode().expect("couldn't enter raw mode");
+	let mut ts = TuiState {
+		my_moniker,
+		game_state,
+		snapshots,
+		mm,
+		poll,
+		events,
+		keys: termion::async_stdin().keys(),
+		out: stdout,
+		last_move_at: Instant::now(),
+		no_change: false,
+		chat_log: ChatLog::new(),
+		chat_input: None,
+		chat_shown: 0,
+		marks: vec![],
+	};
+	write!(ts.out, "{}{}", clear::All, cursor::Hide).unwrap();
+	ts.run();
+	write!(ts.out, "{}{}{}{}", style::Reset, clear::All, cursor::Goto(1, 1), cursor::Show).unwrap();
+	ts.out.flush().unwrap();
+}
+
+struct TuiState {
+	my_moniker: Moniker,
+	game_state: GameState,
+	snapshots: SnapshotBuffer,
+	mm: Middleman,
+	poll: Poll,
+	events: Events,
+	keys: Keys<AsyncReader>,
+	out: RawTerminal<io::Stdout>,
+	last_move_at: Instant,
+	no_change: bool,
+	chat_log: ChatLog<String>,
+	chat_input: Option<String>, // Some while typing
+	chat_shown: usize,
+	marks: Vec<(Moniker, Coord2D, Instant)>, // map pings, with when each arrived
+}
+impl TuiState {
+	fn run(&mut self) {
+		loop {
+			self.handle_server();
+			if !self.handle_keys() {
+				return; // quit
+			}
+			let chat_shown = self.chat_log.shown(Instant::now(), self.chat_input.is_some());
+			if chat_shown != self.chat_shown {
+				self.chat_shown = chat_shown;
+				self.no_change = false;
+			}
+			let marks = self.marks.len();
+			self.marks.retain(|&(_, _, at)| at.elapsed() < MARK_SHOW_TIME);
+			if self.marks.len() != marks {
+				self.no_change = false;
+			}
+			if !self.no_change {
+				self.draw().expect("draw failed");
+				self.no_change = true;
+			}
+		}
+	}
+
+	fn handle_server(&mut self) {
+		self.poll.poll(&mut self.events, Some(POLL_TIMEOUT)).expect("poll failed");
+		if self.events.is_empty() {
+			return;
+		}
+		self.no_change = false;
+		self.events.clear();
+		use self::Clientward::*;
+		let (gs, snapshots, chat_log, marks) = (&mut self.game_state, &mut self.snapshots,
+		                                         &mut self.chat_log, &mut self.marks);
+		let result = self.mm.recv_all_map( |me, msg: Clientward| {
+			trace!("recv kind={}", msg.kind());
+			match msg {
+				Welcome(_) => panic!("Not expecting a welcome"),
+				Refresh(essence) => match GameState::from_essence(essence) {
+					Ok(refreshed) => *gs = refreshed,
+					Err(e) => warn!("refused refresh err={}", e),
+				},
+				Snapshot(id, base, delta) => {
+					if let Some(essence) = snapshots.resolve(id, base, &delta) {
+						match GameState::from_essence(essence) {
+							Ok(resolved) => {
+								*gs = resolved;
+								me.send(& Serverward::AckSnapshot(id)).expect("ack fail");
+							},
+							Err(e) => warn!("refused snapshot id={} err={}", id, e),
+						}
+					}
+				},
+				AddPlayer(moniker, player) => { gs.try_insert_player(moniker, player); },
+				RemovePlayer(moniker) => { gs.try_remove_player(moniker); },
+				UpdMove(moniker, dir) => { gs.move_moniker_in_dir(moniker, dir); },
+				EndProtection(moniker) => { gs.end_protection(moniker); },
+				Pong(..) => (),
+				Mark { from, at } => marks.push((from, at, Instant::now())),
+				Chat { from, text } => {
+					// the server cleans chat, but nothing should get escape codes onto the terminal
+					if let Some(text) = chat::clean(&text) {
+						chat_log.push(format!("{}: {}", drawable(from), text), Instant::now());
+					}
+				},
+				some_err => {
+					error!("server sent err kind={}", some_err.kind());
+					panic!("server sent err");
+				},
+			}
+		}).1;
+		result.expect("Failed to read from server!");
+	}
+
+	// false once the player wants out
+	fn handle_keys(&mut self) -> bool {
+		while let Some(key) = self.keys.next() {
+			let key = key.expect("couldn't read key");
+			if self.chat_input.is_some() {
+				self.chat_key(key);
+				continue;
+			}
+			let dir = match key {
+				Key::Char('\n') => {
+					self.chat_input = Some(String::new());
+					self.no_change = false;
+					continue;
+				},
+				Key::Char('p') => {
+					// no mouse here. ping where I'm standing
+					if let Some(player) = self.game_state.get_player(self.my_moniker) {
+						self.mm.send(& Serverward::Mark(player.coord)).expect("mark fail");
+					}
+					continue;
+				},
+				Key::Char('w') | Key::Up => Direction::Up,
+				Key::Char('a') | Key::Left => Direction::Left,
+				Key::Char('s') | Key::Down => Direction::Down,
+				Key::Char('d') | Key::Right => Direction::Right,
+				Key::Char('q') | Key::Esc | Key::Ctrl('c') => return false,
+				_ => continue,
+			};
+			let move_period = self.game_state.get_player(self.my_moniker)
+				.map(move_period_of)
+				.unwrap_or(MOVE_PERIOD);
+			if self.last_move_at.elapsed() < move_period {
+				continue; // moving too fast
+			}
+			self.last_move_at = Instant::now();
+			self.mm.send(& Serverward::ReqMove(dir)).expect("req fail");
+		}
+		true
+	}
+
+	// while typing, keys edit the message instead of moving
+	fn chat_key(&mut self, key: Key) {
+		match key {
+			Key::Char('\n') => {
+				let input = self.chat_input.take().unwrap_or_default();
+				if let Some(text) = chat::clean(&input) {
+					self.mm.send(& Serverward::Chat(text)).expect("chat fail");
+				}
+			},
+			Key::Esc => self.chat_input = None,
+			Key::Backspace => {
+				if let Some(ref mut input) = self.chat_input {
+					input.pop();
+				}
+			},
+			Key::Char(c) => {
+				if let Some(ref mut input) = self.chat_input {
+					if input.chars().count() < CHAT_MAX_CHARS {
+						input.push(c);
+					}
+				}
+			},
+			_ => return,
+		}
+		self.no_change = false;
+	}
+
+	fn draw(&mut self) -> io::Result<()> {
+		let gs = &self.game_state;
+		let mut frame = String::new();
+		frame.push_str(&format!("{}{}RED {}  BLUE {}{}\r\n",
+			cursor::Goto(1, 1), style::Reset,
+			gs.team_score(Team::Red), gs.team_score(Team::Blue), clear::UntilNewline));
+		for y in 0..gs.bounds().height {
+			for x in 0..gs.bounds().width {
+				frame.push_str(&self.cell(Coord2D::new(x, y)));
+			}
+			frame.push_str(&format!("{}\r\n", style::Reset));
+		}
+		frame.push_str(&format!("{}", style::Reset));
+		let typing = self.chat_input.is_some();
+		for line in self.chat_log.visible(Instant::now(), typing) {
+			frame.push_str(&format!("{}{}\r\n", line, clear::UntilNewline));
+		}
+		match self.chat_input {
+			Some(ref input) => frame.push_str(&format!("> {}_{}", input, clear::UntilNewline)),
+			None => frame.push_str(&format!("WASD/arrows to move, Enter to chat, p to ping, q to quit{}", clear::UntilNewline)),
+		}
+		frame.push_str(&format!("{}", clear::AfterCursor));
+		self.out.write_all(frame.as_bytes())?;
+		self.out.flush()
+	}
+
+	// two characters, with the colours to draw them in
+	fn cell(&self, coord: Coord2D) -> String {
+		let gs = &self.game_state;
+		let bg = match gs.base_at(coord) {
+			Some(team) => format!("{}", color::Bg(BASE_BG[team.index()])),
+			None => format!("{}", color::Bg(color::Reset)),
+		};
+		if let Some(&(from, _, _)) = self.marks.iter().rev().find(|&&(_, at, _)| at == coord) {
+			return format!("{}{}{}{}!{}", style::Reset, style::Bold,
+				color::Bg(MARK_BG), color::Fg(color::Black), drawable(from));
+		}
+		if !gs.player_can_see(self.my_moniker, coord) {
+			return format!("{}{}{}..", style::Reset, bg, color::Fg(FOG_FG));
+		}
+		if gs.is_wall_at(coord) {
+			return format!("{}{}{}##", style::Reset, bg, color::Fg(WALL_FG));
+		}
+		if let Some((&moniker, player)) = gs.player_iter().find(|&(_, p)| p.coord == coord) {
+			let charge = (player.charge as usize).min(CHARGE_FG.len() - 1);
+			let mark = if player.is_protected() { '+' } else { ' ' };
+			return format!("{}{}{}{}{}{}", style::Reset, style::Bold,
+				color::Bg(TEAM_BG[player.team.index()]), color::Fg(CHARGE_FG[charge]),
+				drawable(moniker), mark);
+		}
+		if let Some((_, &kind)) = gs.blob_iter().find(|&(&c, _)| c == coord) {
+			return format!("{}{}{}()", style::Reset, bg, color::Fg(PICKUP_FG[kind.index()]));
+		}
+		let (fg, text) = match gs.tile_at(coord) {
+			Some(Tile::Portal(_)) => (PORTAL_FG, "<>"),
+			Some(Tile::Conveyor(Direction::Up)) => (CONVEYOR_FG, "^^"),
+			Some(Tile::Conveyor(Direction::Down)) => (CONVEYOR_FG, "vv"),
+			Some(Tile::Conveyor(Direction::Left)) => (CONVEYOR_FG, "<<"),
+			Some(Tile::Conveyor(Direction::Right)) => (CONVEYOR_FG, ">>"),
+			Some(Tile::Ice) => (ICE_FG, "~~"),
+			Some(Tile::Pit) => (PIT_FG, "xx"),
+			None => (FOG_FG, "  "),
+		};
+		format!("{}{}{}{}", style::Reset, bg, color::Fg(fg), text)
+	}
+}
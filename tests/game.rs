@@ -0,0 +1,346 @@
+extern crate bincode;
+extern crate pinggame;
+extern crate rand;
+
+use pinggame::common::DIR_CHOICES;
+use pinggame::game::*;
+use pinggame::mapfile::MapFile;
+use pinggame::mapgen::{
+	MapGen,
+	Rooms,
+};
+use pinggame::snapshot::EssenceDelta;
+
+use rand::{
+	Rng,
+	SeedableRng,
+	XorShiftRng,
+};
+
+/*
+Random sequences of joins, moves and leaves, applied to a server's state and
+replayed on a client's the way the client would hear of them. Both must keep
+their invariants after every step and end up with the same essence. A failure
+names the map and seed, so it can be replayed.
+*/
+
+const SEEDS: u32 = 20;
+const STEPS: usize = 300;
+const MONIKERS: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
+
+// small, so players keep bumping into walls, tiles, blobs and each other
+fn small_game(gen: &str, seed: u32) -> GameState {
+	let map = MapSource::Generated {
+		seed: [seed + 1, 2, 3, 4],
+		gen: gen.parse::<MapGen>().unwrap(),
+		bounds: Bounds::new(16, 16),
+	};
+	GameState::new(map, None)
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Op {
+	Add(Moniker, Coord2D, Team),
+	Move(Moniker, Direction),
+	Remove(Moniker),
+}
+
+// the server's side of things. Some(op) is what gets broadcast, if anything
+fn random_op(rng: &mut XorShiftRng, server: &mut GameState) -> Option<Op> {
+	let moniker = Moniker(*rng.choose(&MONIKERS).unwrap());
+	let team = *rng.choose(&[Team::Red, Team::Blue]).unwrap();
+	match rng.gen_range(0, 10) {
+		0 => {
+			// sometimes where the server would spawn them, sometimes anywhere at all
+			let coord = if rng.gen() {
+				server.spawn_spot_for(team, SpawnPolicy::Random)
+			} else {
+				let bounds = server.bounds();
+				Some(Coord2D::new(rng.gen_range(0, bounds.width), rng.gen_range(0, bounds.height)))
+			};
+			match coord {
+				Some(coord) if server.try_add_player(moniker, coord, team) => Some(Op::Add(moniker, coord, team)),
+				_ => None,
+			}
+		},
+		1 => if server.try_remove_player(moniker) { Some(Op::Remove(moniker)) } else { None },
+		_ => {
+			let dir = *rng.choose(&DIR_CHOICES).unwrap();
+			if server.move_moniker_in_dir(moniker, dir) { Some(Op::Move(moniker, dir)) } else { None }
+		},
+	}
+}
+
+fn replay(client: &mut GameState, op: Op) -> bool {
+	match op {
+		Op::Add(moniker, coord, team) => client.try_add_player(moniker, coord, team),
+		Op::Move(moniker, dir) => client.move_moniker_in_dir(moniker, dir),
+		Op::Remove(moniker) => client.try_remove_player(moniker),
+	}
+}
+
+#[test]
+fn random_sessions_keep_invariants_and_replay_identically() {
+	for gen in MapGen::NAMES.iter() {
+		for seed in 0..SEEDS {
+			let mut rng: XorShiftRng = SeedableRng::from_seed([seed + 1, 5, 6, 7]);
+			let mut server = small_game(gen, seed);
+			server.assert_invariants();
+			let mut client = GameState::from_essence(server.get_essence().clone()).unwrap();
+			for step in 0..STEPS {
+				let op = random_op(&mut rng, &mut server);
+				if let Err(msg) = server.check_invariants() {
+					panic!("map {} seed {} step {} after {:?}: server {}", gen, seed, step, op, msg);
+				}
+				if let Some(op) = op {
+					assert!(replay(&mut client, op),
+						"map {} seed {} step {}: client refused {:?}", gen, seed, step, op);
+					if let Err(msg) = client.check_invariants() {
+						panic!("map {} seed {} step {} after {:?}: client {}", gen, seed, step, op, msg);
+					}
+					assert!(client.get_essence() == server.get_essence(),
+						"map {} seed {} step {}: client diverged after {:?}", gen, seed, step, op);
+				}
+			}
+		}
+	}
+}
+
+#[test]
+fn rebuilt_state_keeps_invariants() {
+	for seed in 0..SEEDS {
+		let mut rng: XorShiftRng = SeedableRng::from_seed([seed + 1, 8, 9, 10]);
+		let mut server = small_game("caves", seed);
+		for _ in 0..STEPS {
+			random_op(&mut rng, &mut server);
+		}
+		// what a client joining late would be welcomed with
+		let rebuilt = GameState::from_essence(server.get_essence().clone()).unwrap();
+		if let Err(msg) = rebuilt.check_invariants() {
+			panic!("seed {}: {}", seed, msg);
+		}
+	}
+}
+
+#[test]
+fn players_cannot_be_added_off_the_map() {
+	let mut game_state = small_game("arena", 0);
+	let bounds = game_state.bounds();
+	for &coord in [Coord2D::new(bounds.width, 0), Coord2D::new(0, bounds.height), Coord2D::new(255, 255)].iter() {
+		assert!(!game_state.try_add_player(Moniker('a'), coord, Team::Red));
+	}
+	game_state.assert_invariants();
+}
+
+#[test]
+fn free_spots_are_found_until_the_map_is_nearly_full() {
+	let mut game_state = small_game("caves", 0);
+	let mut sync = false;
+	for i in 0.. {
+		// both searches, from nearly empty to nearly full
+		sync = !sync;
+		let spot = if sync { game_state.sync_random_free_spot() } else { game_state.random_free_spot() };
+		let coord = match spot {
+			Some(coord) => coord,
+			None => break,
+		};
+		assert!(!game_state.is_something_at(coord), "{:?} isn't free", coord);
+		let team = game_state.base_at(coord).unwrap_or(Team::Red);
+		let moniker = Moniker(::std::char::from_u32(0x100 + i).unwrap());
+		assert!(game_state.try_add_player(moniker, coord, team));
+	}
+	assert!(game_state.empty_cell_ratio() < 0.05, "gave up with {} cells free", game_state.num_empty_coords());
+	game_state.assert_invariants();
+}
+
+#[test]
+fn players_cannot_share_a_cell() {
+	let mut game_state = small_game("arena", 0);
+	let coord = game_state.spawn_spot_for(Team::Red, SpawnPolicy::Random).unwrap();
+	assert!(game_state.try_add_player(Moniker('a'), coord, Team::Red));
+	assert!(!game_state.try_add_player(Moniker('b'), coord, Team::Red));
+	let mut twin = game_state.get_player(Moniker('a')).unwrap().clone();
+	assert!(!game_state.try_insert_player(Moniker('b'), twin.clone()));
+	twin.coord = Coord2D::new(255, 255);
+	assert!(!game_state.try_insert_player(Moniker('b'), twin));
+	assert_eq!(game_state.num_players(), 1);
+	game_state.assert_invariants();
+}
+
+#[test]
+fn bad_players_are_left_out_of_a_rebuilt_state() {
+	let game_state = small_game("arena", 0);
+	let coord = Coord2D::new(3, 3);
+	let player = |coord| PlayerObject {
+		coord,
+		charge: 0,
+		team: Team::Red,
+		speed_moves: 0,
+		ghost_moves: 0,
+		protected_moves: 0,
+	};
+	// as if it came off the wire: two on one cell, and one off the map
+	let mut essence = game_state.get_essence().clone();
+	essence.apply_delta(&EssenceDelta {
+		players: vec![
+			(Moniker('b'), player(coord)),
+			(Moniker('a'), player(coord)),
+			(Moniker('c'), player(Coord2D::new(16, 3))),
+		],
+		..EssenceDelta::default()
+	});
+	let rebuilt = GameState::from_essence(essence).unwrap();
+	rebuilt.assert_invariants();
+	assert_eq!(rebuilt.num_players(), 1);
+	assert!(rebuilt.contains_player(Moniker('a')), "the first moniker keeps the cell");
+}
+
+#[test]
+fn bad_blobs_are_left_out_of_a_rebuilt_state() {
+	let game_state = small_game("arena", 0);
+	let blobs = game_state.blob_iter().count();
+	let mut essence = game_state.get_essence().clone();
+	essence.apply_delta(&EssenceDelta {
+		power_blobs: vec![(Coord2D::new(40, 3), PickupKind::Charge), (Coord2D::new(0, 0), PickupKind::Ghost)],
+		..EssenceDelta::default()
+	});
+	let rebuilt = GameState::from_essence(essence).unwrap();
+	assert_eq!(rebuilt.blob_iter().count(), blobs, "off the map, and in the boundary wall");
+	rebuilt.assert_invariants();
+}
+
+/*
+A 16x16 open map seen through fog of radius 4 by `a` at (8, 3): `b` is near,
+`c` too far, and `d` within the radius but behind a wall. Of the three blobs,
+one is in sight, one too far and one behind the same wall.
+*/
+const SEEN_PLAYERS: [(char, u16, u16); 4] = [('a', 8, 3), ('b', 8, 6), ('c', 8, 12), ('d', 12, 3)];
+const SEEN_BLOBS: [(u16, u16); 3] = [(6, 3), (8, 14), (11, 3)];
+
+fn fogged_game() -> GameState {
+	let mut map = MapFile::empty(16, 16);
+	map.walls.push(Coord2D::new(10, 3));
+	map.blob_spawns = SEEN_BLOBS.iter().map(|&(x, y)| Coord2D::new(x, y)).collect();
+	let mut game_state = GameState::new(MapSource::Authored(map), Some(4));
+	for &(c, x, y) in SEEN_PLAYERS.iter() {
+		assert!(game_state.try_add_player(Moniker(c), Coord2D::new(x, y), Team::Red));
+	}
+	game_state
+}
+
+#[test]
+fn fog_leaves_out_what_is_out_of_sight() {
+	let game_state = fogged_game();
+	let view = GameState::from_essence(game_state.essence_visible_to(Moniker('a'))).unwrap();
+	let players: Vec<char> = {
+		let mut players: Vec<char> = view.player_iter().map(|(m, _)| m.0).collect();
+		players.sort();
+		players
+	};
+	assert_eq!(players, vec!['a', 'b']);
+	let blobs: Vec<Coord2D> = view.blob_iter().map(|(&c, _)| c).collect();
+	assert_eq!(blobs, vec![Coord2D::new(6, 3)]);
+	assert!(view.is_wall_at(Coord2D::new(10, 3)), "the wall in the way is itself in sight");
+	// what's left is just as it is on the server
+	assert_eq!(view.get_player(Moniker('b')), game_state.get_player(Moniker('b')));
+	view.assert_invariants();
+}
+
+#[test]
+fn fogged_view_follows_moves() {
+	let mut server = fogged_game();
+	let me = Moniker('a');
+	let mut client = GameState::from_essence(server.essence_visible_to(me)).unwrap();
+	// `b` steps closer, `c` moves where nobody sees, and `a` walks up to the wall
+	let moves = [('b', Direction::Up), ('c', Direction::Down), ('a', Direction::Right)];
+	for &(c, dir) in moves.iter() {
+		assert!(server.move_moniker_in_dir(Moniker(c), dir));
+		if client.contains_player(Moniker(c)) {
+			assert!(client.move_moniker_in_dir(Moniker(c), dir));
+		}
+		assert!(client.essence_visible_to(me) == server.essence_visible_to(me), "out of step after {} moved", c);
+		assert!(server.same_view(&client, me));
+	}
+	assert_eq!(client.get_player(Moniker('b')).unwrap().coord, Coord2D::new(8, 5));
+	assert!(!client.contains_player(Moniker('c')));
+}
+
+#[test]
+fn ghosts_in_walls_take_no_free_cells() {
+	// walled up but for a 3x2 room, whose top row is all blob spawns
+	let mut map = MapFile::empty(11, 11);
+	let bounds = map.bounds();
+	let room = |c: Coord2D| c.x >= 4 && c.x < 7 && c.y >= 5 && c.y < 7;
+	map.walls = bounds.coord_iter().filter(|&c| !room(c)).collect();
+	map.blob_spawns = (4..7).map(|x| Coord2D::new(x, 5)).collect();
+	let game_state = GameState::new(MapSource::Authored(map), None);
+	let ghost = |coord| PlayerObject {
+		coord,
+		charge: 0,
+		team: Team::Red,
+		speed_moves: 0,
+		ghost_moves: PlayerObject::GHOST_MOVES,
+		protected_moves: 0,
+	};
+	// four in walls, and one in the room
+	let mut essence = game_state.get_essence().clone();
+	essence.apply_delta(&EssenceDelta {
+		players: "abcde".chars()
+			.zip([(0, 0), (1, 0), (2, 0), (3, 3), (5, 6)].iter())
+			.map(|(c, &(x, y))| (Moniker(c), ghost(Coord2D::new(x, y))))
+			.collect(),
+		..EssenceDelta::default()
+	});
+	let game_state = GameState::from_essence(essence).unwrap();
+	assert_eq!(game_state.num_players(), 5);
+	assert_eq!(game_state.blob_iter().count(), 3);
+	let free = game_state.coord_iter().filter(|&c| !game_state.is_something_at(c)).count();
+	assert_eq!(free, 2);
+	assert_eq!(game_state.num_empty_coords(), free);
+	game_state.assert_invariants();
+}
+
+// `essence`, made on `own`, as if it came over the wire with `map` instead
+fn with_map(essence: &GameStateEssence, own: &MapSource, map: &MapSource) -> GameStateEssence {
+	let own = bincode::serialize(own).unwrap();
+	let mut bytes = bincode::serialize(essence).unwrap();
+	let at = bytes.windows(own.len()).position(|w| w == &own[..]).unwrap();
+	bytes.splice(at..at + own.len(), bincode::serialize(map).unwrap());
+	bincode::deserialize(&bytes).unwrap()
+}
+
+#[test]
+fn maps_that_dont_fit_are_refused() {
+	let generated = |gen: MapGen, width, height| MapSource::Generated {
+		seed: [1, 2, 3, 4],
+		gen,
+		bounds: Bounds::new(width, height),
+	};
+	let rooms = |min_size, max_size| MapGen::Rooms(Rooms { max_rooms: 8, min_size, max_size });
+	let mut off_map_portal = MapFile::empty(11, 11);
+	off_map_portal.tiles.push((Coord2D::new(5, 5), Tile::Portal(Coord2D::new(11, 5))));
+	let mut off_map_wall = MapFile::empty(11, 11);
+	off_map_wall.walls.push(Coord2D::new(3, 40));
+	let bad = [
+		generated(MapGen::default(), 1, 16),
+		generated(MapGen::default(), 16, 0),
+		generated(MapGen::default(), MapFile::MAX_SIZE + 1, 16),
+		generated(MapGen::default(), u16::MAX, u16::MAX),
+		generated(rooms(7, 3), 16, 16),
+		generated(rooms(3, u16::MAX), 16, 16),
+		MapSource::Authored(off_map_portal),
+		MapSource::Authored(off_map_wall),
+		MapSource::Authored(MapFile::empty(0, 0)),
+	];
+	let own = generated(MapGen::default(), 16, 16);
+	let essence = GameState::new(own.clone(), None).get_essence().clone();
+	for map in bad.iter() {
+		assert!(map.validate().is_err(), "{:?} passed", map);
+		assert!(GameState::from_essence(with_map(&essence, &own, map)).is_err(), "{:?} was built", map);
+	}
+	// the smallest and largest maps still go
+	for &side in [MapFile::MIN_SIZE, MapFile::MAX_SIZE].iter() {
+		let map = generated(rooms(3, 7), side, side);
+		assert!(GameState::from_essence(with_map(&essence, &own, &map)).is_ok(), "{:?} was refused", map);
+	}
+}
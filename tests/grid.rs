@@ -0,0 +1,73 @@
+extern crate bincode;
+extern crate pinggame;
+
+use pinggame::game::Bounds;
+use pinggame::grid::WallFlips;
+use pinggame::mapfile::MapFile;
+
+// wall flips go over the wire as varint gaps. what goes in must come back out
+
+fn flips(indices: &[u32]) -> WallFlips {
+	let mut flips = WallFlips::new();
+	for &index in indices.iter() {
+		flips.toggle(index);
+	}
+	flips
+}
+
+fn round_trip(flips: &WallFlips) -> WallFlips {
+	let bytes = bincode::serialize(flips).expect("won't serialize");
+	bincode::deserialize(&bytes).expect("won't deserialize")
+}
+
+#[test]
+fn empty_flips_round_trip() {
+	let empty = WallFlips::new();
+	assert!(empty.is_empty());
+	let back = round_trip(&empty);
+	assert!(back.is_empty());
+	assert_eq!(back, empty);
+}
+
+#[test]
+fn first_cell_round_trips() {
+	let first = flips(&[0]);
+	assert!(!first.is_empty());
+	assert_eq!(round_trip(&first).indices(), &[0]);
+}
+
+#[test]
+fn long_gaps_round_trip() {
+	// gaps of 127 fit a byte, 128 and up take more
+	let indices = [5, 132, 260, 16644, 16645, 2_000_000];
+	assert_eq!(round_trip(&flips(&indices)).indices(), &indices);
+}
+
+#[test]
+fn last_cell_round_trips() {
+	let bounds = Bounds::new(MapFile::MAX_SIZE, MapFile::MAX_SIZE);
+	let last = bounds.total_coords() as u32 - 1;
+	assert_eq!(round_trip(&flips(&[0, last])).indices(), &[0, last]);
+	assert_eq!(round_trip(&flips(&[u32::MAX])).indices(), &[u32::MAX]);
+}
+
+#[test]
+fn malformed_flips_are_refused() {
+	// cut off mid-varint, a repeated index, and an index past u32
+	let bad: [&[u8]; 3] = [&[0x80], &[3, 0], &[0xff, 0xff, 0xff, 0xff, 0x0f, 1]];
+	for bytes in bad.iter() {
+		assert!(WallFlips::decode(bytes).is_none(), "{:?} was read", bytes);
+		let encoded = bincode::serialize(&bytes.to_vec()).unwrap();
+		assert!(bincode::deserialize::<WallFlips>(&encoded).is_err(), "{:?} was read", bytes);
+	}
+}
+
+#[test]
+fn gaps_past_u32_are_refused() {
+	// the largest gap there is, then the same with a bit more in its fifth byte
+	assert_eq!(WallFlips::decode(&[0xff, 0xff, 0xff, 0xff, 0x0f]).unwrap().indices(), &[u32::MAX]);
+	for &fifth in [0x10, 0x7f].iter() {
+		let bytes = [0xff, 0xff, 0xff, 0xff, fifth];
+		assert!(WallFlips::decode(&bytes).is_none(), "{:?} was read", bytes);
+	}
+}
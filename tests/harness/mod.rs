@@ -0,0 +1,209 @@
+// shared by the integration tests. each test file pulls in what it needs
+#![allow(dead_code)]
+
+use pinggame::common::*;
+use pinggame::game::*;
+use pinggame::mapfile::MapFile;
+use pinggame::server::{
+	Server,
+	ServerConfig,
+};
+use pinggame::clock::ManualClock;
+use pinggame::transport::MemoryTransport;
+
+use middleman::Middleman;
+
+use mio::{
+	Events,
+	Poll,
+	PollOpt,
+	Ready,
+	Token,
+};
+
+use std::{
+	io,
+	net::SocketAddr,
+	sync::Arc,
+	thread,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/////////////////////////////////////////////////////////////
+
+// long enough for a loaded CI box. tests only wait this long when they fail
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+// how long to listen for a message that should never come
+pub const QUIET_TIME: Duration = Duration::from_millis(300);
+
+// an open, walled-in map with no bots, so nothing happens that a test didn't ask for
+pub fn quiet_config() -> ServerConfig {
+	ServerConfig {
+		map_file: Some(MapFile::empty(12, 12)),
+		bots: 0,
+		..ServerConfig::default()
+	}
+}
+
+// runs a server on an ephemeral localhost port until the test process ends
+pub fn start_server(config: ServerConfig) -> SocketAddr {
+	let mut server = Server::bind(&"127.0.0.1:0".parse().unwrap(), config).expect("bind failed");
+	let addr = server.local_addr().expect("no local addr");
+	thread::spawn(move || loop {
+		server.step();
+	});
+	addr
+}
+
+// a server with no sockets, whose time only passes when the test says so
+pub fn sim_server(config: ServerConfig) -> (Server, ManualClock) {
+	let clock = ManualClock::new();
+	let server = Server::new(config, Arc::new(clock.clone())).expect("no server");
+	(server, clock)
+}
+
+// one pass of the server's loop that doesn't wait for anything
+pub fn sim_step(server: &mut Server) {
+	server.step_within(Some(Duration::from_millis(0)));
+}
+
+// connects in memory and says hello, returning the welcome
+pub fn sim_join(server: &mut Server, moniker: char) -> (MemoryTransport, GameState) {
+	let mut client = server.connect_memory();
+	client.send_msg(&Serverward::Hello(Moniker(moniker), None)).unwrap();
+	sim_step(server);
+	match client.recv_msg::<Clientward>().unwrap() {
+		Some(Clientward::Welcome(essence)) => (client, GameState::from_essence(essence).unwrap()),
+		other => panic!("expected a welcome for {:?}, got {:?}", moniker, other),
+	}
+}
+
+// everything that has landed at a simulated client so far
+pub fn sim_drain(client: &mut MemoryTransport) -> Vec<Clientward> {
+	let mut msgs = vec![];
+	while let Some(msg) = client.recv_msg::<Clientward>().expect("link closed") {
+		msgs.push(msg);
+	}
+	msgs
+}
+
+// what a client does with an update, leaving out the drawing
+pub fn sim_apply(game_state: &mut GameState, msg: Clientward) {
+	match msg {
+		Clientward::Refresh(essence) => *game_state = GameState::from_essence(essence).unwrap(),
+		Clientward::AddPlayer(moniker, player) => { game_state.try_insert_player(moniker, player); },
+		Clientward::RemovePlayer(moniker) => { game_state.try_remove_player(moniker); },
+		Clientward::UpdMove(moniker, dir) => { game_state.move_moniker_in_dir(moniker, dir); },
+		Clientward::EndProtection(moniker) => { game_state.end_protection(moniker); },
+		_ => (),
+	}
+}
+
+/*
+A client with no window and no opinions: it sends exactly what the test tells
+it to, and hands over whatever the server sent in the order it arrived.
+*/
+pub struct TestClient {
+	mm: Middleman,
+	probe: MioStream, // the same socket, to see if the server closed it
+	poll: Poll,
+	events: Events,
+}
+impl TestClient {
+	pub fn connect(addr: &SocketAddr) -> Self {
+		let stream = StdStream::connect(addr).expect("connect failed");
+		stream.set_nodelay(true).unwrap();
+		let stream = MioStream::from_stream(stream).unwrap();
+		let probe = stream.try_clone().unwrap();
+		let mm = Middleman::new(stream);
+		let poll = Poll::new().unwrap();
+		poll.register(&mm, Token(0), Ready::readable(), PollOpt::edge()).unwrap();
+		TestClient {
+			mm,
+			probe,
+			poll,
+			events: Events::with_capacity(64),
+		}
+	}
+
+	// connects and says hello, returning the welcome
+	pub fn join(addr: &SocketAddr, moniker: char) -> (Self, GameState) {
+		let mut client = TestClient::connect(addr);
+		client.send(&Serverward::Hello(Moniker(moniker), None));
+		match client.recv() {
+			Some(Clientward::Welcome(essence)) => (client, GameState::from_essence(essence).unwrap()),
+			other => panic!("expected a welcome for {:?}, got {:?}", moniker, other),
+		}
+	}
+
+	pub fn send(&mut self, msg: &Serverward) {
+		self.mm.send(msg).expect("send failed");
+	}
+
+	// the next message, or None if nothing came within TIMEOUT or the server hung up
+	pub fn recv(&mut self) -> Option<Clientward> {
+		self.recv_within(TIMEOUT)
+	}
+
+	pub fn recv_within(&mut self, timeout: Duration) -> Option<Clientward> {
+		self.mm.recv_blocking_solo::<Clientward>(&self.poll, &mut self.events, Some(timeout))
+			.unwrap_or(None)
+	}
+
+	// skips messages until one `pick` likes, panicking if none comes
+	pub fn recv_until<T, F>(&mut self, what: &str, mut pick: F) -> T
+	where F: FnMut(Clientward) -> Option<T> {
+		let deadline = Instant::now() + TIMEOUT;
+		loop {
+			let now = Instant::now();
+			if now >= deadline {
+				panic!("gave up waiting for {}", what);
+			}
+			match self.recv_within(deadline - now) {
+				Some(msg) => if let Some(x) = pick(msg) {
+					return x;
+				},
+				None => panic!("gave up waiting for {}", what),
+			}
+		}
+	}
+
+	// panics if the server says anything for a while
+	pub fn expect_silence(&mut self) {
+		if let Some(msg) = self.recv_within(QUIET_TIME) {
+			panic!("expected silence, got {:?}", msg);
+		}
+	}
+
+	// true once the server has closed the connection. skips whatever it said first
+	pub fn is_hung_up(&mut self) -> bool {
+		let deadline = Instant::now() + TIMEOUT;
+		while Instant::now() < deadline {
+			if self.recv_within(QUIET_TIME).is_some() {
+				continue;
+			}
+			match self.probe.peek(&mut [0u8; 1]) {
+				Ok(0) => return true,
+				Ok(_) => (),
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+				Err(_) => return true,
+			}
+		}
+		false
+	}
+}
+
+// a direction `moniker` can step in without bumping into anything
+pub fn free_direction(game_state: &GameState, moniker: char) -> Direction {
+	let player = game_state.get_player(Moniker(moniker)).expect("no such player");
+	DIR_CHOICES.iter().cloned().find(|&dir| {
+		if game_state.coord_would_exit(player.coord, dir) {
+			return false;
+		}
+		let dest = player.coord.move_with(dir);
+		!game_state.is_something_at(dest) && game_state.team_may_enter(player.team, dest)
+	}).expect("boxed in")
+}
@@ -0,0 +1,176 @@
+extern crate pinggame;
+
+use pinggame::game::*;
+use pinggame::mapfile::{
+	MapError,
+	MapFile,
+};
+
+use std::{
+	env,
+	fs,
+	process,
+};
+
+// the smallest map allowed, with one of everything
+const SMALL_MAP: &str = "\
+// comments and blank lines are skipped
+
+###########
+#.........#
+#.........#
+#.........#
+#.........#
+#.1..S*.^.#
+#....#....#
+#..~.x....#
+#.1.......#
+#.<>v.....#
+###########
+";
+
+// SMALL_MAP as it's written back out
+fn small_map_rows() -> String {
+	SMALL_MAP.lines().skip(2).map(|row| format!("{}\n", row)).collect()
+}
+
+fn parse_err(text: &str) -> MapError {
+	match MapFile::parse(text) {
+		Ok(map) => panic!("expected an error, parsed {:?}", map),
+		Err(e) => e,
+	}
+}
+
+fn validate_err(map: &MapFile) -> MapError {
+	match map.validate() {
+		Ok(()) => panic!("expected an error, {:?} is valid", map),
+		Err(e) => e,
+	}
+}
+
+#[test]
+fn every_kind_of_cell_is_read() {
+	let map = MapFile::parse(SMALL_MAP).unwrap();
+	assert_eq!((map.width, map.height), (11, 11));
+	assert_eq!(map.walls.len(), 40 + 1);
+	assert!(map.walls.contains(&Coord2D::new(5, 6)));
+	assert_eq!(map.spawn_points, vec![Coord2D::new(5, 5)]);
+	assert_eq!(map.blob_spawns, vec![Coord2D::new(6, 5)]);
+	let tiles = map.tile_map();
+	assert_eq!(tiles[&Coord2D::new(2, 5)], Tile::Portal(Coord2D::new(2, 8)));
+	assert_eq!(tiles[&Coord2D::new(2, 8)], Tile::Portal(Coord2D::new(2, 5)));
+	assert_eq!(tiles[&Coord2D::new(8, 5)], Tile::Conveyor(Direction::Up));
+	assert_eq!(tiles[&Coord2D::new(2, 9)], Tile::Conveyor(Direction::Left));
+	assert_eq!(tiles[&Coord2D::new(3, 9)], Tile::Conveyor(Direction::Right));
+	assert_eq!(tiles[&Coord2D::new(4, 9)], Tile::Conveyor(Direction::Down));
+	assert_eq!(tiles[&Coord2D::new(3, 7)], Tile::Ice);
+	assert_eq!(tiles[&Coord2D::new(5, 7)], Tile::Pit);
+	assert_eq!(tiles.len(), 8);
+	map.validate().unwrap();
+}
+
+#[test]
+fn saved_maps_read_back_the_same() {
+	let map = MapFile::parse(SMALL_MAP).unwrap();
+	assert_eq!(map.to_text(), small_map_rows());
+	let path = env::temp_dir().join(format!("pinggame-test-{}.txt", process::id()));
+	map.save(&path).unwrap();
+	let read = MapFile::load(&path);
+	fs::remove_file(&path).unwrap();
+	assert_eq!(read.unwrap(), map);
+	// and one the editor might have put together in any order
+	let mut edited = MapFile::empty(12, 14);
+	edited.tiles.push((Coord2D::new(9, 2), Tile::Portal(Coord2D::new(3, 10))));
+	edited.tiles.push((Coord2D::new(3, 10), Tile::Portal(Coord2D::new(9, 2))));
+	edited.tiles.push((Coord2D::new(6, 6), Tile::Ice));
+	edited.spawn_points.push(Coord2D::new(6, 7));
+	let reread = MapFile::parse(&edited.to_text()).unwrap();
+	assert_eq!(reread.to_text(), edited.to_text());
+	assert_eq!(reread.tile_map(), edited.tile_map());
+}
+
+#[test]
+fn malformed_text_is_refused() {
+	match parse_err("// nothing but a comment\n\n") {
+		MapError::Empty => (),
+		other => panic!("expected Empty, got {:?}", other),
+	}
+	match parse_err("#####\n#...#\n#..#\n#####\n") {
+		MapError::RaggedRow { row: 2 } => (),
+		other => panic!("expected row 2 to be ragged, got {:?}", other),
+	}
+	match parse_err("#####\n#..?#\n#####\n") {
+		MapError::UnknownChar { row: 1, column: 3, found: '?' } => (),
+		other => panic!("expected the `?` to be unknown, got {:?}", other),
+	}
+	match parse_err("#####\n#1.1#\n#.1.#\n#####\n") {
+		MapError::UnpairedPortal('1') => (),
+		other => panic!("expected portal 1 to be unpaired, got {:?}", other),
+	}
+	let too_wide = format!("{}\n", "#".repeat(MapFile::MAX_SIZE as usize + 1));
+	match parse_err(&too_wide) {
+		MapError::BadSize { .. } => (),
+		other => panic!("expected a bad size, got {:?}", other),
+	}
+}
+
+#[test]
+fn boundary_must_be_walled() {
+	let mut map = MapFile::empty(11, 11);
+	map.spawn_points.push(Coord2D::new(5, 5));
+	map.validate().unwrap();
+	map.clear_cell(Coord2D::new(10, 5));
+	match validate_err(&map) {
+		MapError::OpenBoundary(c) => assert_eq!(c, Coord2D::new(10, 5)),
+		other => panic!("expected an open boundary, got {:?}", other),
+	}
+}
+
+#[test]
+fn bases_must_be_open() {
+	let mut map = MapFile::empty(11, 11);
+	map.spawn_points.push(Coord2D::new(5, 5));
+	map.walls.push(Coord2D::new(2, 3));
+	match validate_err(&map) {
+		MapError::WallInBase(c) => assert_eq!(c, Coord2D::new(2, 3)),
+		other => panic!("expected a wall in a base, got {:?}", other),
+	}
+}
+
+#[test]
+fn some_spawn_must_be_reachable() {
+	let mut map = MapFile::empty(11, 11);
+	map.spawn_points.push(Coord2D::new(5, 5));
+	// walled in on all four sides, clear of both bases
+	for &(x, y) in [(5, 4), (4, 5), (6, 5), (5, 6)].iter() {
+		map.walls.push(Coord2D::new(x, y));
+	}
+	match validate_err(&map) {
+		MapError::NoReachableSpawn => (),
+		other => panic!("expected no reachable spawn, got {:?}", other),
+	}
+	// one open spawn is enough
+	map.spawn_points.push(Coord2D::new(7, 2));
+	map.validate().unwrap();
+	map.spawn_points.push(Coord2D::new(5, 4));
+	match validate_err(&map) {
+		MapError::SpawnInWall(c) => assert_eq!(c, Coord2D::new(5, 4)),
+		other => panic!("expected a spawn in a wall, got {:?}", other),
+	}
+}
+
+#[test]
+fn sizes_and_coords_are_checked() {
+	let small = MapFile::MIN_SIZE - 1;
+	match validate_err(&MapFile::empty(small, small)) {
+		MapError::BadSize { width, height } => assert_eq!((width, height), (small, small)),
+		other => panic!("expected a bad size, got {:?}", other),
+	}
+	let mut map = MapFile::empty(11, 11);
+	map.spawn_points.push(Coord2D::new(5, 5));
+	map.blob_spawns.push(Coord2D::new(11, 3));
+	match validate_err(&map) {
+		MapError::OutOfBounds(c) => assert_eq!(c, Coord2D::new(11, 3)),
+		other => panic!("expected out of bounds, got {:?}", other),
+	}
+}
@@ -0,0 +1,182 @@
+extern crate pinggame;
+extern crate middleman;
+extern crate mio;
+
+mod harness;
+
+use harness::*;
+use pinggame::common::*;
+use pinggame::game::*;
+use pinggame::server::{
+	Server,
+	ServerConfig,
+};
+
+use std::{
+	io::{
+		Read,
+		Write,
+	},
+	net::TcpStream,
+	thread,
+};
+
+#[test]
+fn hello_is_welcomed_with_own_player() {
+	let addr = start_server(quiet_config());
+	let (_a, game_state) = TestClient::join(&addr, 'a');
+	assert!(game_state.contains_player(Moniker('a')));
+	assert_eq!(game_state.num_players(), 1);
+}
+
+#[test]
+fn requested_team_is_granted() {
+	let addr = start_server(quiet_config());
+	let mut a = TestClient::connect(&addr);
+	a.send(&Serverward::Hello(Moniker('a'), Some(Team::Blue)));
+	match a.recv() {
+		Some(Clientward::Welcome(essence)) => {
+			let game_state = GameState::from_essence(essence).unwrap();
+			assert_eq!(game_state.get_player(Moniker('a')).unwrap().team, Team::Blue);
+		},
+		other => panic!("expected a welcome, got {:?}", other),
+	}
+}
+
+#[test]
+fn taken_moniker_is_refused() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	let mut impostor = TestClient::connect(&addr);
+	impostor.send(&Serverward::Hello(Moniker('a'), None));
+	match impostor.recv() {
+		Some(Clientward::ErrorTakenMoniker) => (),
+		other => panic!("expected ErrorTakenMoniker, got {:?}", other),
+	}
+	assert!(impostor.is_hung_up());
+	a.expect_silence(); // nobody joined
+}
+
+#[test]
+fn request_before_hello_is_refused() {
+	let addr = start_server(quiet_config());
+	let mut rude = TestClient::connect(&addr);
+	rude.send(&Serverward::ReqMove(Direction::Up));
+	match rude.recv() {
+		Some(Clientward::ErrorExpectedHello) => (),
+		other => panic!("expected ErrorExpectedHello, got {:?}", other),
+	}
+	assert!(rude.is_hung_up());
+}
+
+#[test]
+fn second_hello_is_refused() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	a.send(&Serverward::Hello(Moniker('a'), None));
+	a.recv_until("ErrorExpectedReq", |msg| match msg {
+		Clientward::ErrorExpectedReq => Some(()),
+		_ => None,
+	});
+	assert!(a.is_hung_up());
+}
+
+#[test]
+fn newcomers_are_announced_to_others_only() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	let (mut b, game_state) = TestClient::join(&addr, 'b');
+	assert_eq!(game_state.num_players(), 2);
+	let player = a.recv_until("b to be added", |msg| match msg {
+		Clientward::AddPlayer(Moniker('b'), player) => Some(player),
+		_ => None,
+	});
+	assert_eq!(Some(&player), game_state.get_player(Moniker('b')));
+	b.expect_silence(); // no need to add yourself
+}
+
+#[test]
+fn moves_are_broadcast_to_everyone() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	let (mut b, game_state) = TestClient::join(&addr, 'b');
+	let dir = free_direction(&game_state, 'b');
+	thread::sleep(MOVE_PERIOD); // nobody moves straight after joining
+	b.send(&Serverward::ReqMove(dir));
+	for client in [&mut a, &mut b].iter_mut() {
+		let moved = client.recv_until("b's move", |msg| match msg {
+			Clientward::UpdMove(Moniker('b'), d) => Some(d),
+			_ => None,
+		});
+		assert_eq!(moved, dir);
+	}
+}
+
+#[test]
+fn moving_too_fast_is_ignored() {
+	let addr = start_server(quiet_config());
+	let (mut a, game_state) = TestClient::join(&addr, 'a');
+	let dir = free_direction(&game_state, 'a');
+	thread::sleep(MOVE_PERIOD);
+	a.send(&Serverward::ReqMove(dir));
+	a.send(&Serverward::ReqMove(dir.opposite())); // well within MOVE_PERIOD
+	a.recv_until("a's move", |msg| match msg {
+		Clientward::UpdMove(Moniker('a'), d) if d == dir => Some(()),
+		_ => None,
+	});
+	a.expect_silence();
+}
+
+#[test]
+fn disconnect_removes_player() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	let (b, _) = TestClient::join(&addr, 'b');
+	drop(b);
+	a.recv_until("b to be removed", |msg| match msg {
+		Clientward::RemovePlayer(Moniker('b')) => Some(()),
+		_ => None,
+	});
+	// the moniker is free again
+	let (_b, game_state) = TestClient::join(&addr, 'b');
+	assert_eq!(game_state.num_players(), 2);
+}
+
+#[test]
+fn ping_is_answered_with_tick() {
+	let addr = start_server(quiet_config());
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	a.send(&Serverward::Ping(7, None));
+	a.recv_until("a pong", |msg| match msg {
+		Clientward::Pong(7, _tick) => Some(()),
+		_ => None,
+	});
+}
+
+#[test]
+fn stalled_scrapers_hold_up_nobody() {
+	let config = ServerConfig {
+		metrics_addr: Some("127.0.0.1:0".parse().unwrap()),
+		..quiet_config()
+	};
+	let mut server = Server::bind(&"127.0.0.1:0".parse().unwrap(), config).expect("bind failed");
+	let addr = server.local_addr().unwrap();
+	let metrics_addr = server.metrics_addr().expect("no metrics endpoint");
+	thread::spawn(move || loop {
+		server.step();
+	});
+	let _stalled = TcpStream::connect(metrics_addr).unwrap(); // and never asks for anything
+	let (mut a, _) = TestClient::join(&addr, 'a');
+	a.send(&Serverward::Ping(1, None));
+	a.recv_until("a pong", |msg| match msg {
+		Clientward::Pong(1, _tick) => Some(()),
+		_ => None,
+	});
+	let mut scrape = TcpStream::connect(metrics_addr).unwrap();
+	scrape.set_read_timeout(Some(TIMEOUT)).unwrap();
+	scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+	let mut response = String::new();
+	scrape.read_to_string(&mut response).unwrap();
+	assert!(response.starts_with("HTTP/1.1 200 OK"), "got {:?}", response);
+	assert!(response.contains("pinggame_uptime_seconds"));
+}
@@ -0,0 +1,336 @@
+extern crate pinggame;
+extern crate middleman;
+extern crate mio;
+
+mod harness;
+
+use harness::*;
+use pinggame::chat::{
+	CHAT_MAX_CHARS,
+	CHAT_PERIOD,
+};
+use pinggame::common::*;
+use pinggame::game::*;
+use pinggame::mapfile::MapFile;
+use pinggame::server::ServerConfig;
+use pinggame::snapshot::SnapshotBuffer;
+use pinggame::transport::MemoryTransport;
+
+use std::time::Duration;
+
+// these run on a ManualClock over in-memory links: no sockets, no sleeping
+
+#[test]
+fn session_plays_out_without_waiting() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let (mut b, game_state) = sim_join(&mut server, 'b');
+	sim_step(&mut server);
+	let b_player = game_state.get_player(Moniker('b')).unwrap();
+	assert!(sim_drain(&mut a).iter().any(|msg| match *msg {
+		Clientward::AddPlayer(Moniker('b'), ref player) => player == b_player,
+		_ => false,
+	}));
+	let dir = free_direction(&game_state, 'b');
+	clock.advance(MOVE_PERIOD);
+	b.send_msg(&Serverward::ReqMove(dir)).unwrap();
+	sim_step(&mut server);
+	for client in [&mut a, &mut b].iter_mut() {
+		assert!(sim_drain(client).iter().any(|msg| match *msg {
+			Clientward::UpdMove(Moniker('b'), d) => d == dir,
+			_ => false,
+		}));
+	}
+}
+
+#[test]
+fn moving_too_fast_is_ignored_by_the_clock() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, game_state) = sim_join(&mut server, 'a');
+	let dir = free_direction(&game_state, 'a');
+	a.send_msg(&Serverward::ReqMove(dir)).unwrap(); // straight after joining
+	sim_step(&mut server);
+	assert!(sim_drain(&mut a).is_empty());
+	clock.advance(MOVE_PERIOD);
+	a.send_msg(&Serverward::ReqMove(dir)).unwrap();
+	sim_step(&mut server);
+	match sim_drain(&mut a).as_slice() {
+		&[Clientward::UpdMove(Moniker('a'), d)] if d == dir => (),
+		other => panic!("expected just a's move, got {:?}", other),
+	}
+}
+
+#[test]
+fn latency_delays_delivery() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let mut a = server.connect_memory();
+	a.set_latency(Duration::from_millis(100));
+	a.send_msg(&Serverward::Hello(Moniker('a'), None)).unwrap();
+	sim_step(&mut server);
+	assert_eq!(server.game_state().num_players(), 0); // hello still on its way
+	clock.advance(Duration::from_millis(100));
+	sim_step(&mut server);
+	assert_eq!(server.game_state().num_players(), 1);
+	assert!(sim_drain(&mut a).is_empty()); // welcome still on its way
+	clock.advance(Duration::from_millis(100));
+	match a.recv_msg::<Clientward>().unwrap() {
+		Some(Clientward::Welcome(_)) => (),
+		other => panic!("expected a welcome, got {:?}", other),
+	}
+}
+
+#[test]
+fn held_link_delivers_once_released() {
+	let (mut server, _clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	a.set_held(true);
+	a.send_msg(&Serverward::Ping(1, None)).unwrap();
+	a.send_msg(&Serverward::Ping(2, None)).unwrap();
+	sim_step(&mut server);
+	assert_eq!(a.in_flight(), 2);
+	a.set_held(false);
+	sim_step(&mut server);
+	let pongs: Vec<u32> = sim_drain(&mut a).into_iter().filter_map(|msg| match msg {
+		Clientward::Pong(id, _tick) => Some(id),
+		_ => None,
+	}).collect();
+	assert_eq!(pongs, vec![1, 2]);
+}
+
+#[test]
+fn dropped_link_removes_player() {
+	let (mut server, _clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let (b, _) = sim_join(&mut server, 'b');
+	drop(b);
+	sim_step(&mut server);
+	sim_step(&mut server);
+	assert!(sim_drain(&mut a).iter().any(|msg| matches!(*msg, Clientward::RemovePlayer(Moniker('b')))));
+	assert!(!server.game_state().contains_player(Moniker('b')));
+}
+
+#[test]
+fn standing_still_runs_out_spawn_protection() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, mut game_state) = sim_join(&mut server, 'a');
+	assert!(game_state.get_player(Moniker('a')).unwrap().is_protected());
+	sim_step(&mut server); // the server starts timing on its first tick with `a` in
+	clock.advance(SPAWN_PROTECTION_TIME / 2);
+	sim_step(&mut server);
+	assert!(server.game_state().get_player(Moniker('a')).unwrap().is_protected());
+	clock.advance(SPAWN_PROTECTION_TIME / 2);
+	sim_step(&mut server);
+	assert!(!server.game_state().get_player(Moniker('a')).unwrap().is_protected());
+	for msg in sim_drain(&mut a) {
+		sim_apply(&mut game_state, msg);
+	}
+	assert!(!game_state.get_player(Moniker('a')).unwrap().is_protected());
+	assert!(game_state.get_essence() == server.game_state().get_essence());
+}
+
+#[test]
+fn newcomers_are_refused_once_the_map_is_full() {
+	let (mut server, _clock) = sim_server(quiet_config());
+	let mut joined = vec![]; // kept open, or their players would leave again
+	// more than the free cells of a 12x12 map, so it fills up on the way
+	for c in (b'!'..b'~').map(|b| b as char) {
+		let mut newcomer = server.connect_memory();
+		newcomer.send_msg(&Serverward::Hello(Moniker(c), None)).unwrap();
+		sim_step(&mut server);
+		match newcomer.recv_msg::<Clientward>().unwrap() {
+			Some(Clientward::Welcome(_)) => joined.push(newcomer),
+			Some(Clientward::ErrorGameFull) => {
+				assert!(newcomer.recv_msg::<Clientward>().is_err(), "refused newcomer wasn't hung up on");
+				assert!(!server.game_state().contains_player(Moniker(c)));
+				server.game_state().assert_invariants();
+				return;
+			},
+			other => panic!("expected a welcome or ErrorGameFull for {:?}, got {:?}", c, other),
+		}
+	}
+	panic!("the map never filled up");
+}
+
+#[test]
+fn snapshots_keep_clients_in_step() {
+	let config = ServerConfig {
+		snapshot_period: Some(Duration::from_millis(100)),
+		bots: 2,
+		..quiet_config()
+	};
+	let (mut server, clock) = sim_server(config);
+	let mut a = server.connect_memory();
+	a.send_msg(&Serverward::Hello(Moniker('a'), None)).unwrap();
+	sim_step(&mut server);
+	let mut snapshots = match a.recv_msg::<Clientward>().unwrap() {
+		Some(Clientward::Welcome(essence)) => SnapshotBuffer::new(essence),
+		other => panic!("expected a welcome, got {:?}", other),
+	};
+	let mut resolved = 0;
+	for _ in 0..30 {
+		clock.advance(Duration::from_millis(100));
+		sim_step(&mut server);
+		for msg in sim_drain(&mut a) {
+			if let Clientward::Snapshot(id, base, delta) = msg {
+				let essence = snapshots.resolve(id, base, &delta).expect("base was dropped");
+				assert!(essence == *server.game_state().get_essence(), "snapshot {} came out different", id);
+				resolved += 1;
+				// acking every other one, so some deltas are against older bases
+				if id % 2 == 0 {
+					a.send_msg(&Serverward::AckSnapshot(id)).unwrap();
+				}
+			}
+		}
+	}
+	assert!(resolved > 5, "only {} snapshots while the bots walked about", resolved);
+}
+
+// the chat lines that have landed at a simulated client, as (from, text)
+fn chat_received(client: &mut MemoryTransport) -> Vec<(char, String)> {
+	sim_drain(client).into_iter().filter_map(|msg| match msg {
+		Clientward::Chat { from, text } => Some((from.0, text)),
+		_ => None,
+	}).collect()
+}
+
+#[test]
+fn chat_reaches_everyone_with_sender() {
+	let (mut server, _clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let (mut b, _) = sim_join(&mut server, 'b');
+	a.send_msg(&Serverward::Chat("  hello there \n".to_owned())).unwrap();
+	sim_step(&mut server);
+	for client in [&mut a, &mut b].iter_mut() {
+		assert_eq!(chat_received(client), vec![('a', "hello there".to_owned())]);
+	}
+}
+
+#[test]
+fn chat_is_cut_short_and_blank_chat_dropped() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	a.send_msg(&Serverward::Chat(" \t ".to_owned())).unwrap();
+	sim_step(&mut server);
+	assert!(chat_received(&mut a).is_empty());
+	clock.advance(CHAT_PERIOD);
+	a.send_msg(&Serverward::Chat("x".repeat(CHAT_MAX_CHARS * 2))).unwrap();
+	sim_step(&mut server);
+	assert_eq!(chat_received(&mut a), vec![('a', "x".repeat(CHAT_MAX_CHARS))]);
+}
+
+#[test]
+fn chatting_too_fast_is_ignored() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	a.send_msg(&Serverward::Chat("one".to_owned())).unwrap();
+	a.send_msg(&Serverward::Chat("two".to_owned())).unwrap();
+	sim_step(&mut server);
+	clock.advance(CHAT_PERIOD);
+	a.send_msg(&Serverward::Chat("three".to_owned())).unwrap();
+	sim_step(&mut server);
+	let texts: Vec<String> = chat_received(&mut a).into_iter().map(|(_, text)| text).collect();
+	assert_eq!(texts, vec!["one".to_owned(), "three".to_owned()]);
+}
+
+#[test]
+fn chat_gets_through_fog() {
+	let config = ServerConfig {
+		fog_radius: Some(2),
+		..quiet_config()
+	};
+	let (mut server, _clock) = sim_server(config);
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let (mut b, _) = sim_join(&mut server, 'b');
+	b.send_msg(&Serverward::Chat("over here".to_owned())).unwrap();
+	sim_step(&mut server);
+	assert_eq!(chat_received(&mut a), vec![('b', "over here".to_owned())]);
+}
+
+// the map pings that have landed at a simulated client, as (from, at)
+fn marks_received(client: &mut MemoryTransport) -> Vec<(char, Coord2D)> {
+	sim_drain(client).into_iter().filter_map(|msg| match msg {
+		Clientward::Mark { from, at } => Some((from.0, at)),
+		_ => None,
+	}).collect()
+}
+
+#[test]
+fn marks_reach_everyone_through_fog() {
+	let config = ServerConfig {
+		fog_radius: Some(2),
+		..quiet_config()
+	};
+	let (mut server, _clock) = sim_server(config);
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let (mut b, _) = sim_join(&mut server, 'b');
+	let at = Coord2D::new(1, 1);
+	b.send_msg(&Serverward::Mark(at)).unwrap();
+	sim_step(&mut server);
+	for client in [&mut a, &mut b].iter_mut() {
+		assert_eq!(marks_received(client), vec![('b', at)]);
+	}
+}
+
+#[test]
+fn marking_too_fast_is_ignored() {
+	let (mut server, clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	a.send_msg(&Serverward::Mark(Coord2D::new(1, 1))).unwrap();
+	a.send_msg(&Serverward::Mark(Coord2D::new(2, 2))).unwrap();
+	sim_step(&mut server);
+	clock.advance(MARK_PERIOD);
+	a.send_msg(&Serverward::Mark(Coord2D::new(3, 3))).unwrap();
+	sim_step(&mut server);
+	let ats: Vec<Coord2D> = marks_received(&mut a).into_iter().map(|(_, at)| at).collect();
+	assert_eq!(ats, vec![Coord2D::new(1, 1), Coord2D::new(3, 3)]);
+}
+
+#[test]
+fn marks_off_the_map_are_dropped() {
+	let (mut server, _clock) = sim_server(quiet_config());
+	let (mut a, _) = sim_join(&mut server, 'a');
+	let bounds = server.game_state().bounds();
+	a.send_msg(&Serverward::Mark(Coord2D::new(bounds.width, 0))).unwrap();
+	sim_step(&mut server);
+	assert!(marks_received(&mut a).is_empty());
+	assert!(server.game_state().contains_player(Moniker('a')));
+}
+
+#[test]
+fn fogged_clients_follow_moves_without_refreshing() {
+	let config = ServerConfig {
+		fog_radius: Some(4),
+		map_file: Some(MapFile::empty(20, 20)),
+		bots: 4,
+		..quiet_config()
+	};
+	let (mut server, clock) = sim_server(config);
+	let mut clients: Vec<(char, MemoryTransport, GameState)> = ['a', 'b'].iter().map(|&c| {
+		let (client, game_state) = sim_join(&mut server, c);
+		(c, client, game_state)
+	}).collect();
+	let (mut moves, mut refreshes) = (0, 0);
+	for i in 0..200 {
+		clock.advance(BOT_MOVE_PERIOD);
+		for &mut (c, ref mut client, ref game_state) in clients.iter_mut() {
+			let dir = DIR_CHOICES[(i + c as usize) % 4];
+			if game_state.get_player(Moniker(c)).map(|p| !game_state.coord_would_exit(p.coord, dir)) == Some(true) {
+				client.send_msg(&Serverward::ReqMove(dir)).unwrap();
+			}
+		}
+		sim_step(&mut server);
+		for &mut (c, ref mut client, ref mut game_state) in clients.iter_mut() {
+			for msg in sim_drain(client) {
+				match msg {
+					Clientward::UpdMove(..) => moves += 1,
+					Clientward::Refresh(_) => refreshes += 1,
+					_ => (),
+				}
+				sim_apply(game_state, msg);
+			}
+			assert!(server.game_state().same_view(game_state, Moniker(c)), "{} out of step after {} steps", c, i);
+		}
+	}
+	assert!(moves > 0);
+	assert!(refreshes * 3 < moves, "{} refreshes for {} moves", refreshes, moves);
+}
@@ -0,0 +1,110 @@
+extern crate pinggame;
+extern crate rand;
+
+use pinggame::common::DIR_CHOICES;
+use pinggame::game::*;
+use pinggame::mapgen::MapGen;
+use pinggame::snapshot::*;
+
+use rand::{
+	Rng,
+	SeedableRng,
+	XorShiftRng,
+};
+
+// a run of essences from one game, each a few random moves on from the last
+fn essences(seed: u32, count: usize) -> Vec<GameStateEssence> {
+	let map = MapSource::Generated {
+		seed: [seed + 1, 2, 3, 4],
+		gen: "caves".parse::<MapGen>().unwrap(),
+		bounds: Bounds::new(16, 16),
+	};
+	let mut game_state = GameState::new(map, None);
+	let mut rng: XorShiftRng = SeedableRng::from_seed([seed + 1, 5, 6, 7]);
+	let monikers: Vec<Moniker> = "abcd".chars().map(Moniker).collect();
+	let mut out = vec![game_state.get_essence().clone()];
+	while out.len() < count {
+		let moniker = *rng.choose(&monikers).unwrap();
+		match rng.gen_range(0, 8) {
+			0 => {
+				let team = *rng.choose(&Team::ALL).unwrap();
+				if let Some(coord) = game_state.spawn_spot_for(team, SpawnPolicy::Random) {
+					game_state.try_add_player(moniker, coord, team);
+				}
+			},
+			1 => { game_state.try_remove_player(moniker); },
+			_ => { game_state.move_moniker_in_dir(moniker, *rng.choose(&DIR_CHOICES).unwrap()); },
+		}
+		if game_state.get_essence() != out.last().unwrap() {
+			out.push(game_state.get_essence().clone());
+		}
+	}
+	out
+}
+
+#[test]
+fn deltas_turn_any_base_into_the_target() {
+	for seed in 0..5 {
+		let run = essences(seed, 40);
+		for base in run.iter() {
+			for target in run.iter() {
+				let delta = target.delta_from(base);
+				assert_eq!(delta.is_empty(), target == base);
+				let mut rebuilt = base.clone();
+				rebuilt.apply_delta(&delta);
+				assert!(rebuilt == *target, "seed {}: delta didn't rebuild its target", seed);
+			}
+		}
+	}
+}
+
+// the log and buffer, with every snapshot delivered
+fn send(log: &mut SnapshotLog, buffer: &mut SnapshotBuffer, view: &GameStateEssence) -> (SnapshotId, SnapshotId) {
+	let (id, base, delta) = log.next_snapshot(view.clone()).expect("nothing to send");
+	let rebuilt = buffer.resolve(id, base, &delta).expect("base was dropped");
+	assert!(rebuilt == *view, "snapshot {} against {} came out different", id, base);
+	(id, base)
+}
+
+#[test]
+fn deltas_are_against_the_last_ack() {
+	let run = essences(0, 6);
+	let mut log = SnapshotLog::new(run[0].clone());
+	let mut buffer = SnapshotBuffer::new(run[0].clone());
+	let (first, base) = send(&mut log, &mut buffer, &run[1]);
+	assert_eq!(base, WELCOME_SNAPSHOT);
+	assert!(log.next_snapshot(run[1].clone()).is_none(), "sent the same view twice");
+	let (_, base) = send(&mut log, &mut buffer, &run[2]);
+	assert_eq!(base, WELCOME_SNAPSHOT, "nothing acked yet");
+	log.ack(first);
+	let (second, base) = send(&mut log, &mut buffer, &run[3]);
+	assert_eq!(base, first);
+	log.ack(second);
+	log.ack(first); // late, and older than what's acked already
+	let (_, base) = send(&mut log, &mut buffer, &run[4]);
+	assert_eq!(base, second);
+}
+
+#[test]
+fn acks_older_than_the_window_are_ignored() {
+	let run = essences(1, 30);
+	let mut log = SnapshotLog::new(run[0].clone());
+	let mut buffer = SnapshotBuffer::new(run[0].clone());
+	// far more snapshots than the server remembers, none acked
+	let sent: Vec<SnapshotId> = run[1..25].iter().map(|view| send(&mut log, &mut buffer, view).0).collect();
+	log.ack(sent[0]); // forgotten by now
+	let (_, base) = send(&mut log, &mut buffer, &run[25]);
+	assert_eq!(base, WELCOME_SNAPSHOT, "deltas stay against the last ack the server still has");
+	log.ack(*sent.last().unwrap());
+	let (_, base) = send(&mut log, &mut buffer, &run[26]);
+	assert_eq!(base, *sent.last().unwrap());
+}
+
+#[test]
+fn unknown_bases_are_not_resolved() {
+	let run = essences(2, 3);
+	let mut buffer = SnapshotBuffer::new(run[0].clone());
+	let delta = run[1].delta_from(&run[0]);
+	assert!(buffer.resolve(2, 1, &delta).is_none());
+	assert!(buffer.resolve(1, WELCOME_SNAPSHOT, &delta).is_some());
+}